@@ -0,0 +1,39 @@
+use std::{env, path::PathBuf};
+
+use crate::error::{Error, Result};
+
+#[derive(Debug, Default)]
+pub struct Args {
+    pub config: Option<PathBuf>,
+    pub riot_dir: Option<PathBuf>,
+}
+
+impl Args {
+    pub fn parse() -> Result<Args> {
+        let mut args = Args::default();
+        let mut values = env::args_os().skip(1);
+        while let Some(arg) = values.next() {
+            match arg.to_str() {
+                Some("--config") => {
+                    let value = values
+                        .next()
+                        .ok_or_else(|| Error::Usage("--config requires a path".to_owned()))?;
+                    args.config = Some(PathBuf::from(value));
+                }
+                Some("--riot-dir") => {
+                    let value = values
+                        .next()
+                        .ok_or_else(|| Error::Usage("--riot-dir requires a path".to_owned()))?;
+                    args.riot_dir = Some(PathBuf::from(value));
+                }
+                _ => {
+                    return Err(Error::Usage(format!(
+                        "unknown argument {}",
+                        arg.to_string_lossy()
+                    )))
+                }
+            }
+        }
+        Ok(args)
+    }
+}
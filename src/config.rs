@@ -0,0 +1,349 @@
+use std::{
+    error::Error,
+    fmt::Display,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde::{de::Error as _, Deserialize, Deserializer};
+
+use crate::discord::template::Template;
+
+/// The documented config which is written on first run. It also holds the defaults of every
+/// setting a config leaves out.
+pub const DEFAULT_CONFIG: &str = include_str!("config.default.toml");
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub app_id: i64,
+    pub riot_dir: Option<PathBuf>,
+    pub maps_file: Option<PathBuf>,
+    pub queues_file: Option<PathBuf>,
+    pub behavior: BehaviorConfig,
+    pub show: ShowConfig,
+    pub activity: ActivityConfig,
+    #[serde(default)]
+    pub buttons: Vec<ButtonConfig>,
+    pub log: LogConfig,
+    pub debug: DebugConfig,
+}
+
+/// The config of the user, which only has to contain the settings which differ from the
+/// default config.
+///
+/// It is deserialized straight from the user's text, so every error points at its line.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    app_id: Option<i64>,
+    riot_dir: Option<PathBuf>,
+    maps_file: Option<PathBuf>,
+    queues_file: Option<PathBuf>,
+    behavior: BehaviorFile,
+    show: ShowFile,
+    activity: ActivityFile,
+    #[serde(deserialize_with = "deserialize_buttons")]
+    buttons: Option<Vec<ButtonConfig>>,
+    log: LogFile,
+    debug: DebugFile,
+}
+
+/// Declares a section of the config, along with its counterpart in [`ConfigFile`] whose
+/// settings are all optional, and how the settings which are set replace the defaults.
+macro_rules! section {
+    (
+        pub struct $name:ident / $file:ident {
+            $(pub $field:ident: $type:ty,)*
+        }
+    ) => {
+        #[derive(Deserialize, Debug)]
+        #[serde(deny_unknown_fields)]
+        pub struct $name {
+            $(pub $field: $type,)*
+        }
+
+        #[derive(Deserialize, Default)]
+        #[serde(deny_unknown_fields)]
+        struct $file {
+            $($field: Option<$type>,)*
+        }
+
+        impl $name {
+            fn apply(&mut self, file: $file) {
+                $(
+                    if let Some(value) = file.$field {
+                        self.$field = value;
+                    }
+                )*
+            }
+        }
+    };
+}
+
+section! {
+    pub struct BehaviorConfig / BehaviorFile {
+        pub disconnect_on_game_stop: bool,
+        pub ask_to_join: bool,
+        pub clear_after_idle_minutes: Option<u64>,
+    }
+}
+
+section! {
+    pub struct ShowConfig / ShowFile {
+        pub party: bool,
+        pub map_image: bool,
+        pub state: bool,
+        pub timestamps: bool,
+        pub rank: bool,
+        pub agent: bool,
+        pub riot_id: bool,
+    }
+}
+
+section! {
+    pub struct LogConfig / LogFile {
+        pub level: String,
+        pub json: bool,
+        pub file: bool,
+        pub dir: Option<PathBuf>,
+    }
+}
+
+section! {
+    pub struct DebugConfig / DebugFile {
+        pub dump_dir: Option<PathBuf>,
+    }
+}
+
+// Activity texts replace the default text as a whole, rather than per line.
+section! {
+    pub struct ActivityConfig / ActivityFile {
+        pub in_game: ActivityText,
+        pub custom_game: ActivityText,
+        pub range: ActivityText,
+        pub pregame: ActivityText,
+        pub menu: ActivityText,
+        pub idle: ActivityText,
+        pub queue: ActivityText,
+        pub custom_setup: ActivityText,
+        pub custom_queue: ActivityText,
+        pub unknown: ActivityText,
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ActivityText {
+    pub details: Template,
+    pub state: Option<Template>,
+}
+
+/// Discord shows at most this many buttons.
+pub const MAX_BUTTONS: usize = 2;
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ButtonConfig {
+    pub label: Template,
+    pub url: Template,
+    /// The statuses the button is shown in, all of them if not set.
+    pub show_in: Option<Vec<ButtonStatus>>,
+    #[serde(default)]
+    pub show_in_competitive: bool,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ButtonStatus {
+    Menu,
+    Idle,
+    Queue,
+    Pregame,
+    InGame,
+    Unknown,
+}
+
+impl Config {
+    /// Loads the config from `path`, writing the default config there first if it doesn't exist.
+    pub fn load(path: &Path) -> Result<Config, ConfigError> {
+        let error = |kind| ConfigError {
+            path: path.to_path_buf(),
+            kind,
+        };
+        if !path.exists() {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).map_err(|e| error(ConfigErrorKind::Io(e)))?;
+            }
+            fs::write(path, DEFAULT_CONFIG).map_err(|e| error(ConfigErrorKind::Io(e)))?;
+        }
+        let content = fs::read_to_string(path).map_err(|e| error(ConfigErrorKind::Io(e)))?;
+        Config::parse(&content).map_err(|e| error(ConfigErrorKind::Parse(e)))
+    }
+
+    /// Parses a config, taking every setting it leaves out from the default config.
+    fn parse(content: &str) -> Result<Config, toml::de::Error> {
+        let file: ConfigFile = toml::from_str(content)?;
+        let mut config = Config::default();
+        config.app_id = file.app_id.unwrap_or(config.app_id);
+        config.riot_dir = file.riot_dir.or(config.riot_dir);
+        config.maps_file = file.maps_file.or(config.maps_file);
+        config.queues_file = file.queues_file.or(config.queues_file);
+        config.behavior.apply(file.behavior);
+        config.show.apply(file.show);
+        config.activity.apply(file.activity);
+        config.buttons = file.buttons.unwrap_or(config.buttons);
+        config.log.apply(file.log);
+        config.debug.apply(file.debug);
+        Ok(config)
+    }
+
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join(env!("CARGO_PKG_NAME")).join("config.toml"))
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        toml::from_str(DEFAULT_CONFIG).expect("bundled default config is invalid")
+    }
+}
+
+fn deserialize_buttons<'de, D>(deserializer: D) -> Result<Option<Vec<ButtonConfig>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let buttons = Vec::<ButtonConfig>::deserialize(deserializer)?;
+    if buttons.len() > MAX_BUTTONS {
+        return Err(D::Error::custom(format!(
+            "Discord shows at most {} buttons",
+            MAX_BUTTONS
+        )));
+    }
+    Ok(Some(buttons))
+}
+
+#[derive(Debug)]
+pub struct ConfigError {
+    pub path: PathBuf,
+    pub kind: ConfigErrorKind,
+}
+
+#[derive(Debug)]
+pub enum ConfigErrorKind {
+    Io(io::Error),
+    Parse(toml::de::Error),
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.kind {
+            ConfigErrorKind::Io(e) => f.write_fmt(format_args!(
+                "unable to read {}: {}",
+                self.path.display(),
+                e
+            )),
+            ConfigErrorKind::Parse(e) => f.write_fmt(format_args!(
+                "invalid config {}: {}",
+                self.path.display(),
+                e
+            )),
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_config_parses() {
+        let config = Config::default();
+        assert_eq!(config.app_id, 944668216486154291);
+        assert!(!config.behavior.ask_to_join);
+        assert!(config.show.party);
+        assert!(!config.show.riot_id);
+        assert_eq!(config.log.level, "info");
+        assert!(config.activity.pregame.state.is_some());
+    }
+
+    #[test]
+    fn empty_config_uses_defaults() {
+        let config = Config::parse("").unwrap();
+        assert!(config.behavior.disconnect_on_game_stop);
+        assert!(config.activity.in_game.state.is_some());
+    }
+
+    #[test]
+    fn sections_are_merged() {
+        let config = Config::parse("[show]\nrank = false\n").unwrap();
+        assert!(!config.show.rank);
+        assert!(config.show.agent);
+    }
+
+    #[test]
+    fn activity_texts_are_replaced() {
+        let config = Config::parse("[activity.in_game]\ndetails = \"Playing\"\n").unwrap();
+        assert!(config.activity.in_game.state.is_none());
+        assert!(config.activity.pregame.state.is_some());
+    }
+
+    #[test]
+    fn parses_buttons() {
+        let config = Config::parse(
+            r#"
+            [[buttons]]
+            label = "Profile"
+            url = "https://example.com/{name}"
+            show_in = ["menu", "in_game"]
+
+            [[buttons]]
+            label = "Stream"
+            url = "https://example.com/stream"
+            show_in_competitive = true
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.buttons.len(), 2);
+        assert_eq!(
+            config.buttons[0].show_in,
+            Some(vec![ButtonStatus::Menu, ButtonStatus::InGame])
+        );
+        assert!(!config.buttons[0].show_in_competitive);
+        assert_eq!(config.buttons[1].show_in, None);
+        assert!(config.buttons[1].show_in_competitive);
+        assert!(Config::default().buttons.is_empty());
+    }
+
+    #[test]
+    fn rejects_more_buttons_than_discord_shows() {
+        let button = "[[buttons]]\nlabel = \"A\"\nurl = \"https://example.com\"\n";
+        assert!(Config::parse(&button.repeat(3)).is_err());
+    }
+
+    fn error_line(content: &str) -> Option<usize> {
+        Config::parse(content)
+            .unwrap_err()
+            .line_col()
+            .map(|(line, _)| line + 1)
+    }
+
+    #[test]
+    fn errors_point_at_their_line() {
+        assert_eq!(error_line("[behavior]\nask_to_join = \"yes\"\n"), Some(2));
+        // Invalid templates and unknown settings point at their table and name the setting.
+        let content = "[show]\nparty = true\n\n[activity.menu]\ndetails = \"{if map\"\n";
+        assert_eq!(error_line(content), Some(4));
+        let error = Config::parse(content).unwrap_err().to_string();
+        assert!(error.contains("activity.menu.details"), "{}", error);
+        assert_eq!(error_line("app_id = 1\n\n[show]\nranks = false\n"), Some(3));
+    }
+
+    #[test]
+    fn unknown_settings_are_rejected() {
+        assert!(Config::parse("[show]\nranks = false\n").is_err());
+        assert!(Config::parse("colour = true\n").is_err());
+    }
+}
@@ -1,72 +1,603 @@
 use std::num::NonZeroU32;
 
-use discord_sdk::activity::{ActivityArgs, ActivityBuilder, Assets};
+use discord_sdk::activity::{ActivityArgs, ActivityBuilder, Assets, PartyPrivacy};
+use serde::Serialize;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use tracing::warn;
 
-use crate::valorant::game_state::{
-    GameMode::{Competitive, CustomGame, Deathmatch, Replication, SpikeRush, Unknown, Unrated},
-    GameState,
-    GameStateStatus::{InGame, Menu, PreGame},
+use super::{
+    join::join_secrets,
+    template::{clamp, Context},
 };
+use crate::{
+    config::{ActivityText, ButtonConfig, ButtonStatus, Config},
+    valorant::{
+        game_state::{
+            GameState,
+            GameStateStatus::{InGame, Menu, PreGame, Unknown as UnknownStatus},
+        },
+        maps::MapType,
+        queues::QueueCategory,
+        session::Session,
+    },
+};
+
+/// Discord rejects button labels and urls longer than this many bytes.
+const MAX_BUTTON_LABEL: usize = 32;
+const MAX_BUTTON_URL: usize = 512;
+
+/// A button below the activity, which discord-sdk 0.3 doesn't know about.
+#[derive(Serialize, Debug)]
+struct Button {
+    label: String,
+    url: String,
+}
 
 macro_rules! game_mode {
     ($game_mode:expr) => {
-        match $game_mode {
-            Competitive => "In a Competitive Match",
-            CustomGame => "In a Custom Game",
-            Deathmatch => "Playing Deathmatch",
-            Replication => "Playing Replication",
-            SpikeRush => "Playing Spike Rush",
-            Unknown => "Unknown Gamemode",
-            Unrated => "Playing Unrated",
-        }
+        $game_mode.info.activity.as_str()
     };
 }
 
-pub fn build_activity(state: &GameState) -> impl Into<ActivityArgs> {
-    let mut activity = ActivityBuilder::default();
-    let game_mode = &state.game_mode;
-    match state.status {
-        InGame => {
-            if state.map.display_name == "The Range" {
-                activity = activity.details("In the Range");
-            } else {
-                activity = activity
-                    .details(format!(
-                        "{} ({} - {})",
-                        game_mode!(game_mode),
-                        state.scores.ally_team,
-                        state.scores.enemy_team
-                    ))
-                    .state(format!("on {}", state.map.display_name));
-            }
+/// Builds the arguments of the `SET_ACTIVITY` command which shows the state of the game.
+pub fn build_activity(state: &GameState, session: Option<&Session>, config: &Config) -> Value {
+    let text = select_text(state, config);
+    // The Riot ID is only shared if the player explicitly wants to.
+    let session = session.filter(|_| config.show.riot_id);
+    let context = context(state, session, config);
+    let mut activity = ActivityBuilder::default().details(text.details.render(&context));
+    if config.show.state {
+        if let Some(template) = &text.state {
+            activity = activity.state(template.render(&context));
+        }
+    }
+    if config.show.timestamps {
+        if let Some(started_at) = state.started_at {
+            activity = activity.start_timestamp(started_at);
+        }
+    }
+    let mut assets = Assets::default();
+    if config.show.map_image {
+        if let Some(image_key) = &state.map.image_key {
+            let text = match session {
+                Some(session) => format!(
+                    "{} | Playing as {}",
+                    state.map.display_name,
+                    session.riot_id()
+                ),
+                None => state.map.display_name.clone(),
+            };
+            assets = assets.large(image_key, Some(text));
         }
-        PreGame => {
-            activity = activity
-                .details("In Agent Select")
-                .state(game_mode!(game_mode));
+    }
+    let rank = state.rank.as_ref().filter(|_| config.show.rank);
+    let agent = state.agent.as_ref().filter(|_| config.show.agent);
+    // The agent takes the small image while it is known, the rank moves to its hover text.
+    match (agent, rank) {
+        (Some(agent), Some(rank)) => {
+            assets = assets.small(
+                &agent.icon_key,
+                Some(format!("{} | {}", agent.name, rank.name)),
+            );
+        }
+        (Some(agent), None) => assets = assets.small(&agent.icon_key, Some(&agent.name)),
+        (None, Some(rank)) => assets = assets.small(&rank.icon_key, Some(&rank.name)),
+        (None, None) => {}
+    }
+    if assets.large_image.is_some() || assets.small_image.is_some() {
+        activity = activity.assets(assets);
+    }
+    let mut joinable = false;
+    if let Some(party_id) = state.party.id.as_ref().filter(|_| config.show.party) {
+        let privacy = if state.party.open {
+            PartyPrivacy::Public
+        } else {
+            PartyPrivacy::Private
+        };
+        if config.behavior.ask_to_join {
+            if let Some(secrets) = join_secrets(state) {
+                activity = activity.secrets(secrets);
+                joinable = true;
+            }
         }
-        Menu { in_queue } => {
-            if in_queue {
-                activity = activity.details(match game_mode {
-                    CustomGame => "Joining a Custom Game".to_owned(),
-                    _ => format!("Queuing {}", game_mode.get_display_name()),
-                });
-            } else {
-                activity = activity.details(match game_mode {
-                    CustomGame => "Setting up a Custom Game".to_owned(),
-                    _ => format!("Hovering {}", game_mode.get_display_name()),
-                });
+        activity = activity.party(
+            discord_party_id(party_id),
+            NonZeroU32::new(state.party.size),
+            NonZeroU32::new(state.party.max_size),
+            privacy,
+        );
+    }
+    let mut buttons = Vec::new();
+    // Discord doesn't allow buttons together with join secrets.
+    if !joinable {
+        let url_context = context.url_encoded();
+        for button in config
+            .buttons
+            .iter()
+            .filter(|button| shows_button(button, state))
+        {
+            let label = clamp(&button.label.render(&context), MAX_BUTTON_LABEL);
+            let url = button.url.render_unclamped(&url_context);
+            if url.len() > MAX_BUTTON_URL {
+                warn!("Leaving out the button {}, its url is too long", label);
+            } else if !label.is_empty() && !url.is_empty() {
+                buttons.push(Button { label, url });
             }
         }
+    }
+    activity_args(activity.into(), &buttons)
+}
+
+/// Serializes an activity for `SET_ACTIVITY`, adding the buttons discord-sdk can't send.
+fn activity_args(args: ActivityArgs, buttons: &[Button]) -> Value {
+    let mut args = serde_json::to_value(args).expect("activities are valid json");
+    if let Some(activity) = args.get_mut("activity").and_then(Value::as_object_mut) {
+        if !buttons.is_empty() {
+            activity.insert("buttons".to_owned(), json!(buttons));
+        }
+    }
+    args
+}
+
+/// Whether a button is shown in the current status of the game.
+fn shows_button(button: &ButtonConfig, state: &GameState) -> bool {
+    if state.game_mode.info.category == QueueCategory::Competitive && !button.show_in_competitive {
+        return false;
+    }
+    let status = match &state.status {
+        InGame => ButtonStatus::InGame,
+        PreGame => ButtonStatus::Pregame,
+        Menu { in_queue: true, .. } => ButtonStatus::Queue,
+        Menu { idle: true, .. } => ButtonStatus::Idle,
+        Menu { .. } => ButtonStatus::Menu,
+        UnknownStatus(_) => ButtonStatus::Unknown,
     };
-    if let Some(image_key) = &state.map.image_key {
-        activity = activity
-            .assets(Assets::default().large(image_key, Some(state.map.display_name.clone())));
-    }
-    activity.party(
-        "uwu",
-        NonZeroU32::new(state.party.size),
-        NonZeroU32::new(state.party.max_size),
-        discord_sdk::activity::PartyPrivacy::Private,
-    )
+    button
+        .show_in
+        .as_ref()
+        .is_none_or(|statuses| statuses.contains(&status))
+}
+
+/// Salt for party ids, shared by every installation so party members end up with the same id.
+const PARTY_ID_SALT: &[u8] = b"valorant-discord-rich-presence/party";
+
+/// Derives the Discord party id from the Riot party id without revealing the latter.
+pub fn discord_party_id(riot_party_id: &str) -> String {
+    hash_id(PARTY_ID_SALT, riot_party_id)
+}
+
+/// Hashes a Riot id with a salt, so it can be shared without revealing the id.
+pub fn hash_id(salt: &[u8], id: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(salt);
+    hasher.update(id.as_bytes());
+    hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn select_text<'a>(state: &GameState, config: &'a Config) -> &'a ActivityText {
+    let texts = &config.activity;
+    let custom = state.game_mode.is_custom();
+    match &state.status {
+        InGame if state.map.map_type == MapType::Range => &texts.range,
+        InGame if custom => &texts.custom_game,
+        InGame => &texts.in_game,
+        PreGame => &texts.pregame,
+        Menu { in_queue: true, .. } if custom => &texts.custom_queue,
+        Menu { in_queue: true, .. } => &texts.queue,
+        Menu { idle: true, .. } => &texts.idle,
+        Menu { .. } if custom => &texts.custom_setup,
+        Menu { .. } => &texts.menu,
+        UnknownStatus(_) => &texts.unknown,
+    }
+}
+
+fn context(state: &GameState, session: Option<&Session>, config: &Config) -> Context {
+    let game_mode = &state.game_mode;
+    let scores = &state.scores;
+    let mut context = Context::default();
+    context.value("mode", game_mode.get_display_name());
+    context.value("activity", game_mode!(game_mode));
+    context.value("map", &state.map.display_name);
+    context.value("ally", scores.ally_team.to_string());
+    context.value("enemy", scores.enemy_team.to_string());
+    context.value("party_size", state.party.size.to_string());
+    context.value("party_max", state.party.max_size.to_string());
+    if let Some(rank) = state.rank.as_ref().filter(|_| config.show.rank) {
+        context.value("rank", &rank.name);
+    }
+    if let Some(agent) = state.agent.as_ref().filter(|_| config.show.agent) {
+        context.value("agent", &agent.name);
+        context.flag("agent_locked", agent.locked);
+    } else {
+        context.flag("agent_locked", false);
+    }
+    if let Some(session) = session {
+        context.value("name", &session.game_name);
+        context.value("tag", &session.game_tag);
+        context.value("riot_id", session.riot_id());
+    }
+    if let Some(custom) = &state.custom {
+        if let Some(name) = &custom.name {
+            context.value("custom_name", name);
+        }
+        if let Some(team) = custom.team {
+            context.value("custom_team", team.role());
+        }
+    }
+    context.flag("winning", scores.ally_team > scores.enemy_team);
+    context.flag("losing", scores.ally_team < scores.enemy_team);
+    context.flag("tied", scores.ally_team == scores.enemy_team);
+    context.flag("in_party", state.party.size > 1);
+    context.flag("has_rounds", game_mode.info.rounds);
+    context
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::discord::template::Template;
+    use crate::valorant::{
+        agents::determine_agent,
+        game_state::{CustomGame, CustomTeam, Party, Scores},
+        maps::determine_map,
+        ranks::determine_rank,
+        testing::{game_state, menu},
+    };
+
+    const ASCENT: &str = "/Game/Maps/Ascent/Ascent";
+    const RANGE: &str = "/Game/Maps/PovegliaV2/RangeV2";
+
+    fn text<'a>(config: &'a Config, name: &str) -> &'a ActivityText {
+        let texts = &config.activity;
+        match name {
+            "in_game" => &texts.in_game,
+            "custom_game" => &texts.custom_game,
+            "range" => &texts.range,
+            "pregame" => &texts.pregame,
+            "menu" => &texts.menu,
+            "idle" => &texts.idle,
+            "queue" => &texts.queue,
+            "custom_setup" => &texts.custom_setup,
+            "custom_queue" => &texts.custom_queue,
+            "unknown" => &texts.unknown,
+            _ => unreachable!("unknown activity text {}", name),
+        }
+    }
+
+    #[test]
+    fn selects_text_for_every_status() {
+        let config = Config::default();
+        let unknown = || UnknownStatus("REPLAY".to_owned());
+        let cases = [
+            (InGame, "competitive", ASCENT, "in_game"),
+            (InGame, "custom", ASCENT, "custom_game"),
+            (InGame, "unrated", RANGE, "range"),
+            (InGame, "custom", RANGE, "range"),
+            (PreGame, "competitive", ASCENT, "pregame"),
+            (PreGame, "custom", ASCENT, "pregame"),
+            (menu(false, false), "competitive", ASCENT, "menu"),
+            (menu(false, false), "custom", ASCENT, "custom_setup"),
+            (menu(true, false), "competitive", ASCENT, "queue"),
+            (menu(true, false), "custom", ASCENT, "custom_queue"),
+            (menu(false, true), "competitive", ASCENT, "idle"),
+            (menu(false, true), "custom", ASCENT, "idle"),
+            // Queuing players are shown as queuing even while they are away.
+            (menu(true, true), "competitive", ASCENT, "queue"),
+            (menu(true, true), "custom", ASCENT, "custom_queue"),
+            (unknown(), "competitive", ASCENT, "unknown"),
+            (unknown(), "custom", ASCENT, "unknown"),
+        ];
+        for (status, queue_id, map, expected) in cases {
+            let description = format!("{:?} in {} on {}", status, queue_id, map);
+            let state = game_state(status, queue_id, map);
+            assert!(
+                std::ptr::eq(select_text(&state, &config), text(&config, expected)),
+                "{} should use {}",
+                description,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn context_compares_scores() {
+        let config = Config::default();
+        let mut state = game_state(InGame, "competitive", ASCENT);
+        for (ally, enemy, winning, losing, tied) in [
+            (7, 5, true, false, false),
+            (5, 7, false, true, false),
+            (6, 6, false, false, true),
+        ] {
+            state.scores = Scores {
+                ally_team: ally,
+                enemy_team: enemy,
+            };
+            let context = super::context(&state, None, &config);
+            assert_eq!(context.flags["winning"], winning);
+            assert_eq!(context.flags["losing"], losing);
+            assert_eq!(context.flags["tied"], tied);
+            assert_eq!(context.values["ally"], ally.to_string());
+            assert_eq!(context.values["enemy"], enemy.to_string());
+        }
+    }
+
+    #[test]
+    fn context_describes_the_game_mode_and_party() {
+        let config = Config::default();
+        let mut state = game_state(menu(false, false), "deathmatch", ASCENT);
+        let context = super::context(&state, None, &config);
+        assert_eq!(context.values["mode"], "Deathmatch");
+        assert_eq!(context.values["map"], "Ascent");
+        assert!(!context.flags["has_rounds"]);
+        assert!(!context.flags["in_party"]);
+        state.party.size = 3;
+        let context = super::context(&state, None, &config);
+        assert!(context.flags["in_party"]);
+        assert_eq!(context.values["party_size"], "3");
+        assert_eq!(context.values["party_max"], "5");
+    }
+
+    #[test]
+    fn context_respects_show_settings() {
+        let mut config = Config::default();
+        let mut state = game_state(PreGame, "competitive", ASCENT);
+        state.rank = determine_rank(19);
+        state.agent = determine_agent("add6443a-41bd-e414-f6ad-e58d267f4e95", true);
+        let context = super::context(&state, None, &config);
+        assert_eq!(context.values["rank"], "Diamond 2");
+        assert_eq!(context.values["agent"], "Jett");
+        assert!(context.flags["agent_locked"]);
+        config.show.rank = false;
+        config.show.agent = false;
+        let context = super::context(&state, None, &config);
+        assert!(!context.values.contains_key("rank"));
+        assert!(!context.values.contains_key("agent"));
+        assert!(!context.flags["agent_locked"]);
+    }
+
+    #[test]
+    fn context_contains_the_riot_id_of_the_session() {
+        let config = Config::default();
+        let state = game_state(menu(false, false), "unrated", ASCENT);
+        let session = Session {
+            puuid: "puuid".to_owned(),
+            game_name: "Name".to_owned(),
+            game_tag: "TAG".to_owned(),
+        };
+        let context = super::context(&state, Some(&session), &config);
+        assert_eq!(context.values["name"], "Name");
+        assert_eq!(context.values["tag"], "TAG");
+        assert_eq!(context.values["riot_id"], "Name#TAG");
+        let context = super::context(&state, None, &config);
+        assert!(!context.values.contains_key("riot_id"));
+    }
+
+    #[test]
+    fn context_describes_custom_games() {
+        let config = Config::default();
+        let mut state = game_state(menu(false, false), "custom", ASCENT);
+        state.custom = Some(CustomGame {
+            name: Some("Scrim".to_owned()),
+            team: Some(CustomTeam::Spectators),
+        });
+        let context = super::context(&state, None, &config);
+        assert_eq!(context.values["custom_name"], "Scrim");
+        assert_eq!(context.values["custom_team"], "Spectating");
+        state.custom = Some(CustomGame {
+            name: None,
+            team: None,
+        });
+        let context = super::context(&state, None, &config);
+        assert!(!context.values.contains_key("custom_name"));
+        assert!(!context.values.contains_key("custom_team"));
+    }
+
+    #[test]
+    fn renders_default_texts() {
+        let config = Config::default();
+        let mut state = game_state(InGame, "competitive", ASCENT);
+        state.scores = Scores {
+            ally_team: 7,
+            enemy_team: 5,
+        };
+        state.agent = determine_agent("add6443a-41bd-e414-f6ad-e58d267f4e95", true);
+        let text = select_text(&state, &config);
+        let context = super::context(&state, None, &config);
+        assert_eq!(
+            text.details.render(&context),
+            "In a Competitive Match (7 - 5)"
+        );
+        assert_eq!(
+            text.state.as_ref().unwrap().render(&context),
+            "Jett on Ascent"
+        );
+
+        let mut state = game_state(PreGame, "unrated", ASCENT);
+        state.agent = determine_agent("add6443a-41bd-e414-f6ad-e58d267f4e95", false);
+        let text = select_text(&state, &config);
+        let context = super::context(&state, None, &config);
+        assert_eq!(
+            text.state.as_ref().unwrap().render(&context),
+            "Playing Unrated as Jett (hovering)"
+        );
+    }
+
+    #[test]
+    fn renders_default_custom_texts() {
+        let config = Config::default();
+        let render = |state: &GameState| {
+            let text = select_text(state, &config);
+            let context = super::context(state, None, &config);
+            let state = text.state.as_ref().map(|state| state.render(&context));
+            (text.details.render(&context), state.unwrap_or_default())
+        };
+        let mut state = game_state(menu(false, false), "custom", ASCENT);
+        state.custom = Some(CustomGame {
+            name: None,
+            team: Some(CustomTeam::TeamA),
+        });
+        assert_eq!(render(&state).1, "Team A on Ascent");
+        // Members of the lobby may know neither their team nor the map.
+        state.custom = Some(CustomGame {
+            name: None,
+            team: None,
+        });
+        state.map = determine_map("");
+        assert_eq!(render(&state).1, "In the Lobby");
+
+        state.status = InGame;
+        assert_eq!(render(&state).0, "Custom: Playing");
+        state.map = determine_map(ASCENT);
+        state.custom = Some(CustomGame {
+            name: Some("Scrim".to_owned()),
+            team: Some(CustomTeam::TeamB),
+        });
+        assert_eq!(
+            render(&state),
+            (
+                "Custom: Team B on Ascent".to_owned(),
+                "Scrim (0 - 0)".to_owned()
+            )
+        );
+    }
+
+    fn button(label: &str, url: &str) -> ButtonConfig {
+        ButtonConfig {
+            label: Template::parse(label).unwrap(),
+            url: Template::parse(url).unwrap(),
+            show_in: None,
+            show_in_competitive: false,
+        }
+    }
+
+    fn buttons(state: &GameState, session: Option<&Session>, config: &Config) -> Vec<String> {
+        let args = build_activity(state, session, config);
+        args["activity"]["buttons"]
+            .as_array()
+            .map(|buttons| {
+                buttons
+                    .iter()
+                    .map(|button| {
+                        let label = button["label"].as_str().unwrap();
+                        format!("{} {}", label, button["url"].as_str().unwrap())
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn shows_buttons_with_encoded_urls() {
+        let mut config = Config::default();
+        config.show.riot_id = true;
+        config.buttons = vec![
+            button(
+                "{name}'s Profile",
+                "{if riot_id}https://example.com/{name}%23{tag}{end}",
+            ),
+            button("Stream", "https://example.com/stream"),
+        ];
+        let session = Session {
+            puuid: "puuid".to_owned(),
+            game_name: "Some Name".to_owned(),
+            game_tag: "TAG".to_owned(),
+        };
+        let state = game_state(menu(false, false), "unrated", ASCENT);
+        assert_eq!(
+            buttons(&state, Some(&session), &config),
+            [
+                "Some Name's Profile https://example.com/Some%20Name%23TAG",
+                "Stream https://example.com/stream"
+            ]
+        );
+        // Without a shared Riot ID, the url of the profile is empty.
+        config.show.riot_id = false;
+        assert_eq!(
+            buttons(&state, Some(&session), &config),
+            ["Stream https://example.com/stream"]
+        );
+    }
+
+    #[test]
+    fn clamps_button_labels() {
+        let config = Config {
+            buttons: vec![button(&"a".repeat(40), "https://example.com")],
+            ..Config::default()
+        };
+        let state = game_state(menu(false, false), "unrated", ASCENT);
+        let label = format!("{}… https://example.com", "a".repeat(29));
+        assert_eq!(buttons(&state, None, &config), [label]);
+    }
+
+    #[test]
+    fn leaves_out_buttons_with_too_long_urls() {
+        let url = format!("https://example.com/{}", "a".repeat(500));
+        let config = Config {
+            buttons: vec![button("Profile", &url)],
+            ..Config::default()
+        };
+        let state = game_state(menu(false, false), "unrated", ASCENT);
+        assert!(buttons(&state, None, &config).is_empty());
+        // Without buttons, the activity doesn't have an empty list of them either.
+        assert_eq!(
+            build_activity(&state, None, &config)["activity"].get("buttons"),
+            None
+        );
+    }
+
+    #[test]
+    fn shows_buttons_in_the_configured_statuses() {
+        let mut config = Config::default();
+        let mut profile = button("Profile", "https://example.com");
+        profile.show_in = Some(vec![ButtonStatus::Menu, ButtonStatus::InGame]);
+        config.buttons = vec![profile];
+        let shown =
+            |status| !buttons(&game_state(status, "unrated", ASCENT), None, &config).is_empty();
+        assert!(shown(menu(false, false)));
+        assert!(shown(InGame));
+        assert!(!shown(menu(true, false)));
+        assert!(!shown(menu(false, true)));
+        assert!(!shown(PreGame));
+        assert!(!shown(UnknownStatus("REPLAY".to_owned())));
+    }
+
+    #[test]
+    fn hides_buttons_in_competitive_unless_enabled() {
+        let mut config = Config {
+            buttons: vec![button("Profile", "https://example.com")],
+            ..Config::default()
+        };
+        for queue_id in ["competitive", "premier"] {
+            let state = game_state(InGame, queue_id, ASCENT);
+            assert!(buttons(&state, None, &config).is_empty());
+            config.buttons[0].show_in_competitive = true;
+            assert_eq!(buttons(&state, None, &config).len(), 1);
+            config.buttons[0].show_in_competitive = false;
+        }
+    }
+
+    #[test]
+    fn hides_buttons_while_friends_can_ask_to_join() {
+        let mut config = Config {
+            buttons: vec![button("Profile", "https://example.com")],
+            ..Config::default()
+        };
+        let mut state = game_state(menu(false, false), "unrated", ASCENT);
+        state.party = Party {
+            id: Some("party".to_owned()),
+            size: 2,
+            max_size: 5,
+            open: true,
+        };
+        assert_eq!(buttons(&state, None, &config).len(), 1);
+        config.behavior.ask_to_join = true;
+        assert!(buttons(&state, None, &config).is_empty());
+        state.party.open = false;
+        assert_eq!(buttons(&state, None, &config).len(), 1);
+    }
 }
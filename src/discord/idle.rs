@@ -0,0 +1,117 @@
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use crate::valorant::game_state::GameStateStatus;
+
+/// Decides when the activity of a player who is away in the menus is cleared.
+///
+/// The timer starts when the player goes idle in the menus and stops when they come back.
+/// Players who queue while away are about to play, so they don't start it.
+pub struct IdleTimer {
+    clear_after: Option<Duration>,
+    deadline: Option<Instant>,
+    cleared: bool,
+}
+
+impl IdleTimer {
+    pub fn new(clear_after_minutes: Option<u64>) -> IdleTimer {
+        IdleTimer {
+            clear_after: clear_after_minutes.map(|minutes| Duration::from_secs(minutes * 60)),
+            deadline: None,
+            cleared: false,
+        }
+    }
+
+    /// Follows the status of the player, which was reported at `now`.
+    pub fn update(&mut self, status: &GameStateStatus, now: Instant) {
+        let away = matches!(
+            status,
+            GameStateStatus::Menu {
+                idle: true,
+                in_queue: false
+            }
+        );
+        if !away {
+            self.reset();
+        } else if self.deadline.is_none() && !self.cleared {
+            self.deadline = self.clear_after.map(|after| now + after);
+        }
+    }
+
+    /// When the activity is cleared, if the player is away.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Marks the activity as cleared once the deadline passed, until the player comes back.
+    pub fn expire(&mut self) {
+        self.deadline = None;
+        self.cleared = true;
+    }
+
+    /// Whether the activity was cleared because the player is away.
+    pub fn is_cleared(&self) -> bool {
+        self.cleared
+    }
+
+    /// Forgets about the player, like when the game stopped.
+    pub fn reset(&mut self) {
+        self.deadline = None;
+        self.cleared = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::valorant::testing::menu;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn starts_when_the_player_goes_away() {
+        let mut timer = IdleTimer::new(Some(5));
+        let start = Instant::now();
+        timer.update(&menu(false, false), start);
+        assert_eq!(timer.deadline(), None);
+        timer.update(&menu(false, true), start);
+        assert_eq!(timer.deadline(), Some(start + 5 * MINUTE));
+        // Later presences of an away player don't push the deadline back.
+        timer.update(&menu(false, true), start + MINUTE);
+        assert_eq!(timer.deadline(), Some(start + 5 * MINUTE));
+    }
+
+    #[test]
+    fn doesnt_start_while_queued() {
+        let mut timer = IdleTimer::new(Some(5));
+        let start = Instant::now();
+        timer.update(&menu(true, true), start);
+        assert_eq!(timer.deadline(), None);
+        // Queuing stops a running timer as well.
+        timer.update(&menu(false, true), start);
+        timer.update(&menu(true, true), start + MINUTE);
+        assert_eq!(timer.deadline(), None);
+    }
+
+    #[test]
+    fn stays_cleared_until_the_player_comes_back() {
+        let mut timer = IdleTimer::new(Some(5));
+        let start = Instant::now();
+        timer.update(&menu(false, true), start);
+        timer.expire();
+        assert!(timer.is_cleared());
+        timer.update(&menu(false, true), start + 6 * MINUTE);
+        assert!(timer.is_cleared());
+        assert_eq!(timer.deadline(), None);
+        timer.update(&menu(false, false), start + 7 * MINUTE);
+        assert!(!timer.is_cleared());
+    }
+
+    #[test]
+    fn never_starts_when_disabled() {
+        let mut timer = IdleTimer::new(None);
+        timer.update(&menu(false, true), Instant::now());
+        assert_eq!(timer.deadline(), None);
+    }
+}
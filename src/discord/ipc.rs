@@ -0,0 +1,334 @@
+use std::time::Duration;
+
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    time::timeout,
+};
+use tracing::debug;
+
+use crate::error::{Error, Result};
+
+const HANDSHAKE: u32 = 0;
+const FRAME: u32 = 1;
+const CLOSE: u32 = 2;
+const PING: u32 = 3;
+const PONG: u32 = 4;
+
+/// Discord answers commands right away, a connection which doesn't is dropped.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+trait Pipe: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Pipe for T {}
+
+/// A connection to the local Discord client which only sends activities.
+///
+/// discord-sdk 0.3 can't send activity buttons, so activities are sent as raw `SET_ACTIVITY`
+/// commands over a connection of their own, while discord-sdk keeps track of whether Discord
+/// is running and receives the activity events. The connection is opened on the first
+/// activity and re-opened when Discord restarted.
+pub struct ActivitySocket {
+    client_id: i64,
+    /// The sockets or named pipes Discord may listen on, in the order Discord claims them.
+    paths: Vec<String>,
+    pipe: Option<Box<dyn Pipe>>,
+    nonce: u64,
+}
+
+impl ActivitySocket {
+    pub fn new(client_id: i64) -> ActivitySocket {
+        ActivitySocket::with_paths(client_id, socket_paths())
+    }
+
+    fn with_paths(client_id: i64, paths: Vec<String>) -> ActivitySocket {
+        ActivitySocket {
+            client_id,
+            paths,
+            pipe: None,
+            nonce: 0,
+        }
+    }
+
+    /// Sends the arguments of a `SET_ACTIVITY` command, like `{"pid": 1, "activity": {...}}`.
+    ///
+    /// Leaving out the activity clears it.
+    pub async fn set_activity(&mut self, args: Value) -> Result<Value> {
+        // A connection which was opened before may have been closed by a restarted Discord.
+        if self.pipe.is_some() {
+            match self.command("SET_ACTIVITY", &args).await {
+                Err(Error::DiscordIpc(e)) => debug!("Reconnecting to Discord: {}", e),
+                result => return result,
+            }
+        }
+        self.command("SET_ACTIVITY", &args).await
+    }
+
+    /// Closes the connection, which clears the activity it set.
+    pub fn close(&mut self) {
+        self.pipe = None;
+    }
+
+    async fn command(&mut self, cmd: &str, args: &Value) -> Result<Value> {
+        let result = self.try_command(cmd, args).await;
+        if let Err(Error::DiscordIpc(_)) = result {
+            self.pipe = None;
+        }
+        result
+    }
+
+    async fn try_command(&mut self, cmd: &str, args: &Value) -> Result<Value> {
+        if self.pipe.is_none() {
+            self.connect().await?;
+        }
+        self.nonce += 1;
+        let nonce = self.nonce.to_string();
+        let pipe = self.pipe.as_mut().expect("connected above");
+        let command = json!({ "cmd": cmd, "args": args, "nonce": nonce });
+        write_frame(pipe, FRAME, &command).await?;
+        loop {
+            let response = read_response(pipe).await?;
+            if response["nonce"].as_str() != Some(&nonce) {
+                continue;
+            }
+            if response["evt"] == "ERROR" {
+                return Err(Error::DiscordRejected(
+                    response["data"]["message"]
+                        .as_str()
+                        .unwrap_or("unknown error")
+                        .to_owned(),
+                ));
+            }
+            return Ok(response["data"].clone());
+        }
+    }
+
+    async fn connect(&mut self) -> Result<()> {
+        let mut last_error = None;
+        for path in &self.paths {
+            match open(path).await {
+                Ok(pipe) => {
+                    let mut pipe: Box<dyn Pipe> = Box::new(pipe);
+                    let handshake = json!({ "v": 1, "client_id": self.client_id.to_string() });
+                    write_frame(&mut pipe, HANDSHAKE, &handshake).await?;
+                    // Discord confirms the handshake with the READY event.
+                    read_response(&mut pipe).await?;
+                    debug!("Connected to Discord at {} to send activities", path);
+                    self.pipe = Some(pipe);
+                    return Ok(());
+                }
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(Error::DiscordIpc(match last_error {
+            Some(e) => format!("unable to connect: {}", e),
+            None => "unable to connect".to_owned(),
+        }))
+    }
+}
+
+#[cfg(unix)]
+async fn open(path: &str) -> std::io::Result<tokio::net::UnixStream> {
+    tokio::net::UnixStream::connect(path).await
+}
+
+#[cfg(windows)]
+async fn open(path: &str) -> std::io::Result<tokio::net::windows::named_pipe::NamedPipeClient> {
+    tokio::net::windows::named_pipe::ClientOptions::new().open(path)
+}
+
+/// Where Discord listens, the first instance takes `discord-ipc-0`, the next one `-1` and so on.
+fn socket_paths() -> Vec<String> {
+    let dir = if cfg!(windows) {
+        r"\\?\pipe".to_owned()
+    } else {
+        ["XDG_RUNTIME_DIR", "TMPDIR", "TMP", "TEMP"]
+            .iter()
+            .find_map(|name| std::env::var(name).ok())
+            .unwrap_or_else(|| "/tmp".to_owned())
+    };
+    let separator = if cfg!(windows) { r"\" } else { "/" };
+    (0..10)
+        .map(|id| format!("{}{}discord-ipc-{}", dir, separator, id))
+        .collect()
+}
+
+fn ipc_error(e: impl std::fmt::Display) -> Error {
+    Error::DiscordIpc(e.to_string())
+}
+
+async fn write_frame(pipe: &mut Box<dyn Pipe>, opcode: u32, payload: &Value) -> Result<()> {
+    write_bytes(pipe, opcode, payload.to_string().as_bytes()).await
+}
+
+/// Frames are the opcode and the length of the json payload as little endian, then the payload.
+async fn write_bytes(pipe: &mut Box<dyn Pipe>, opcode: u32, payload: &[u8]) -> Result<()> {
+    let mut frame = Vec::with_capacity(8 + payload.len());
+    frame.extend_from_slice(&opcode.to_le_bytes());
+    frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    frame.extend_from_slice(payload);
+    pipe.write_all(&frame).await.map_err(ipc_error)
+}
+
+/// Reads frames until Discord sends a response or an event, answering its pings.
+async fn read_response(pipe: &mut Box<dyn Pipe>) -> Result<Value> {
+    loop {
+        let (opcode, payload) = timeout(RESPONSE_TIMEOUT, read_frame(pipe))
+            .await
+            .map_err(|_| ipc_error("Discord didn't answer"))??;
+        match opcode {
+            FRAME => return serde_json::from_slice(&payload).map_err(ipc_error),
+            PING => write_bytes(pipe, PONG, &payload).await?,
+            CLOSE => {
+                let reason: Value = serde_json::from_slice(&payload).unwrap_or_default();
+                return Err(ipc_error(format!(
+                    "Discord closed the connection: {}",
+                    reason["message"].as_str().unwrap_or("no reason")
+                )));
+            }
+            _ => {}
+        }
+    }
+}
+
+async fn read_frame(pipe: &mut Box<dyn Pipe>) -> Result<(u32, Vec<u8>)> {
+    let mut header = [0; 8];
+    pipe.read_exact(&mut header).await.map_err(ipc_error)?;
+    let opcode = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+    let length = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+    let mut payload = vec![0; length as usize];
+    pipe.read_exact(&mut payload).await.map_err(ipc_error)?;
+    Ok((opcode, payload))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use tempfile::TempDir;
+    use tokio::net::UnixListener;
+
+    use super::*;
+
+    type Frames = Arc<Mutex<Vec<(u32, Value)>>>;
+
+    /// Stands in for Discord, confirming handshakes and answering commands with `respond`.
+    ///
+    /// Records every frame it receives, and closes each connection after one command if
+    /// `restart` is set, like a restarting Discord does.
+    fn serve(dir: &TempDir, respond: fn(&Value) -> Value, restart: bool) -> (String, Frames) {
+        let path = dir.path().join("discord-ipc-0");
+        let listener = UnixListener::bind(&path).unwrap();
+        let frames = Frames::default();
+        let recorded = frames.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let mut pipe: Box<dyn Pipe> = Box::new(stream);
+                while let Ok((opcode, payload)) = read_frame(&mut pipe).await {
+                    let payload: Value = serde_json::from_slice(&payload).unwrap();
+                    recorded.lock().unwrap().push((opcode, payload.clone()));
+                    let response = match opcode {
+                        HANDSHAKE => json!({ "cmd": "DISPATCH", "evt": "READY", "data": {} }),
+                        _ => {
+                            let mut response = respond(&payload);
+                            response["nonce"] = payload["nonce"].clone();
+                            response
+                        }
+                    };
+                    write_frame(&mut pipe, FRAME, &response).await.unwrap();
+                    if restart && opcode == FRAME {
+                        break;
+                    }
+                }
+            }
+        });
+        (path.to_str().unwrap().to_owned(), frames)
+    }
+
+    fn accept(command: &Value) -> Value {
+        json!({ "cmd": command["cmd"], "evt": null, "data": command["args"]["activity"] })
+    }
+
+    fn reject(command: &Value) -> Value {
+        json!({ "cmd": command["cmd"], "evt": "ERROR", "data": { "code": 4000, "message": "invalid button" } })
+    }
+
+    fn handshakes(frames: &Frames) -> usize {
+        let frames = frames.lock().unwrap();
+        frames
+            .iter()
+            .filter(|(opcode, _)| *opcode == HANDSHAKE)
+            .count()
+    }
+
+    fn activity(details: &str) -> Value {
+        json!({
+            "pid": 1,
+            "activity": {
+                "details": details,
+                "buttons": [{ "label": "Profile", "url": "https://example.com" }],
+            },
+        })
+    }
+
+    #[tokio::test]
+    async fn sends_activities_after_the_handshake() {
+        let dir = TempDir::new().unwrap();
+        let (path, frames) = serve(&dir, accept, false);
+        let missing = dir.path().join("missing").to_str().unwrap().to_owned();
+        let mut socket = ActivitySocket::with_paths(42, vec![missing, path]);
+        let shown = socket.set_activity(activity("Menu")).await.unwrap();
+        assert_eq!(shown, activity("Menu")["activity"]);
+        socket.set_activity(activity("Queue")).await.unwrap();
+
+        let frames = frames.lock().unwrap();
+        assert_eq!(frames[0], (HANDSHAKE, json!({ "v": 1, "client_id": "42" })));
+        let (opcode, command) = &frames[2];
+        assert_eq!(*opcode, FRAME);
+        assert_eq!(command["cmd"], "SET_ACTIVITY");
+        assert_eq!(command["args"], activity("Queue"));
+        // The connection is kept open.
+        assert_eq!(frames.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn reports_rejected_activities() {
+        let dir = TempDir::new().unwrap();
+        let (path, frames) = serve(&dir, reject, false);
+        let mut socket = ActivitySocket::with_paths(42, vec![path]);
+        for _ in 0..2 {
+            assert!(matches!(
+                socket.set_activity(activity("Menu")).await,
+                Err(Error::DiscordRejected(message)) if message == "invalid button"
+            ));
+        }
+        assert_eq!(handshakes(&frames), 1);
+    }
+
+    #[tokio::test]
+    async fn reconnects_after_discord_restarted() {
+        let dir = TempDir::new().unwrap();
+        let (path, frames) = serve(&dir, accept, true);
+        let mut socket = ActivitySocket::with_paths(42, vec![path]);
+        socket.set_activity(activity("Menu")).await.unwrap();
+        socket.set_activity(activity("Queue")).await.unwrap();
+        assert_eq!(handshakes(&frames), 2);
+    }
+
+    #[tokio::test]
+    async fn fails_without_discord() {
+        let dir = TempDir::new().unwrap();
+        let path = dir
+            .path()
+            .join("discord-ipc-0")
+            .to_str()
+            .unwrap()
+            .to_owned();
+        let mut socket = ActivitySocket::with_paths(42, vec![path]);
+        assert!(matches!(
+            socket.set_activity(activity("Menu")).await,
+            Err(Error::DiscordIpc(_))
+        ));
+    }
+}
@@ -0,0 +1,167 @@
+use discord_sdk::activity::{events::ActivityEvent, Secrets};
+use tracing::info;
+
+use super::activity::hash_id;
+use crate::{
+    error::{Error, Result},
+    valorant::{api::RiotApi, decode::decode_private, game_state::GameState},
+};
+
+/// Salt for join secrets, so they differ from the Discord party id of the same party.
+const JOIN_SECRET_SALT: &[u8] = b"valorant-discord-rich-presence/join";
+
+/// Builds the secret Discord hands to friends whose request to join we accepted.
+///
+/// Like the party id, the secret doesn't reveal the Riot party id. Friends find the party
+/// through the presences their own Riot Client shows them, so only Riot friends can use it.
+pub fn join_secrets(state: &GameState) -> Option<Secrets> {
+    let party_id = state.party.id.as_ref().filter(|_| state.party.open)?;
+    if state.party.size >= state.party.max_size {
+        return None;
+    }
+    Some(Secrets {
+        join: Some(join_secret(party_id)),
+        ..Default::default()
+    })
+}
+
+fn join_secret(riot_party_id: &str) -> String {
+    hash_id(JOIN_SECRET_SALT, riot_party_id)
+}
+
+/// Bridges "Ask to Join" on Discord to the Riot party.
+///
+/// Requests to join our party are answered by the player in Discord. Once a friend accepted
+/// our request, we join their party through the Riot Client.
+pub async fn handle_activity_event(event: ActivityEvent, api: Option<&dyn RiotApi>) -> Result<()> {
+    match event {
+        ActivityEvent::JoinRequest(request) => {
+            info!(
+                "{} asked to join your party, the request can be answered in Discord.",
+                request.user.username
+            );
+        }
+        ActivityEvent::Join(join) => {
+            let api = api
+                .ok_or_else(|| Error::RiotClient("not connected to the Riot Client".to_owned()))?;
+            join_party(api, &join.secret).await?;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Joins the party of the Riot friend whose party the secret was made for.
+async fn join_party(api: &dyn RiotApi, secret: &str) -> Result<()> {
+    let presences = api.presences().await?;
+    let party_id = presences
+        .iter()
+        .filter(|presence| presence.product == "valorant")
+        .find_map(|presence| {
+            let party_id = decode_private(presence).ok()?.party_id?;
+            (join_secret(&party_id) == secret).then_some(party_id)
+        })
+        .ok_or_else(|| Error::InvalidJoinSecret(secret.to_owned()))?;
+    // Riot IDs of friends don't belong into log files which end up in bug reports.
+    info!("Joining the party of a Riot friend...");
+    api.join_party(&party_id).await
+}
+
+#[cfg(test)]
+mod tests {
+    use discord_sdk::activity::events::{JoinRequestEvent, SecretEvent};
+
+    use super::*;
+    use crate::valorant::{
+        game_state::Party,
+        testing::{game_state, menu, StandInApi},
+    };
+
+    const PARTY_ID: &str = "5a3b6c2e-1f4d-4e8a-9b7c-0d2e3f4a5b6c";
+
+    fn state(party_id: Option<&str>, size: u32, open: bool) -> GameState {
+        GameState {
+            party: Party {
+                id: party_id.map(str::to_owned),
+                size,
+                max_size: 5,
+                open,
+            },
+            ..game_state(menu(false, false), "unrated", "")
+        }
+    }
+
+    fn secret_of(state: &GameState) -> Option<String> {
+        join_secrets(state).and_then(|secrets| secrets.join)
+    }
+
+    fn join_event(secret: String) -> ActivityEvent {
+        ActivityEvent::Join(SecretEvent { secret })
+    }
+
+    #[test]
+    fn only_open_parties_with_room_have_a_secret() {
+        assert!(secret_of(&state(Some(PARTY_ID), 2, true)).is_some());
+        assert!(secret_of(&state(Some(PARTY_ID), 2, false)).is_none());
+        assert!(secret_of(&state(Some(PARTY_ID), 5, true)).is_none());
+        assert!(secret_of(&state(None, 1, true)).is_none());
+    }
+
+    #[test]
+    fn secret_doesnt_reveal_the_party_id() {
+        let secret = secret_of(&state(Some(PARTY_ID), 2, true)).unwrap();
+        assert!(!secret.contains(PARTY_ID));
+        assert_eq!(secret, join_secret(PARTY_ID));
+        assert_ne!(secret, join_secret("another-party"));
+        assert_ne!(secret, crate::discord::activity::discord_party_id(PARTY_ID));
+    }
+
+    #[tokio::test]
+    async fn joins_the_party_of_the_friend_the_secret_belongs_to() {
+        let api = StandInApi {
+            friends: vec![
+                ("Solo", "EUW", None),
+                ("Other", "NA1", Some("another-party")),
+                ("Friend", "EUW", Some(PARTY_ID)),
+            ],
+            ..Default::default()
+        };
+        let secret = secret_of(&state(Some(PARTY_ID), 2, true)).unwrap();
+        handle_activity_event(join_event(secret), Some(&api))
+            .await
+            .unwrap();
+        assert_eq!(*api.joined.lock().unwrap(), [PARTY_ID]);
+    }
+
+    #[tokio::test]
+    async fn rejects_secrets_of_strangers() {
+        let api = StandInApi {
+            friends: vec![("Other", "NA1", Some("another-party"))],
+            ..Default::default()
+        };
+        let secret = join_secret(PARTY_ID);
+        let result = handle_activity_event(join_event(secret), Some(&api)).await;
+        assert!(matches!(result, Err(Error::InvalidJoinSecret(_))));
+        assert!(api.joined.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn joining_needs_the_riot_client() {
+        let result = handle_activity_event(join_event(join_secret(PARTY_ID)), None).await;
+        assert!(matches!(result, Err(Error::RiotClient(_))));
+    }
+
+    #[tokio::test]
+    async fn join_requests_are_left_to_the_player() {
+        let api = StandInApi::default();
+        // The user only deserializes from borrowed strings.
+        let request: JoinRequestEvent = serde_json::from_str(
+            r#"{"user": {"id": "123456789", "username": "friend", "discriminator": "0", "bot": false}}"#,
+        )
+        .unwrap();
+        handle_activity_event(ActivityEvent::JoinRequest(request), Some(&api))
+            .await
+            .unwrap();
+        assert!(api.joined.lock().unwrap().is_empty());
+    }
+}
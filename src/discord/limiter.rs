@@ -0,0 +1,211 @@
+use std::{collections::VecDeque, time::Duration};
+
+use serde::Serialize;
+use serde_json::Value;
+use tokio::time::Instant;
+
+/// Discord accepts this many activity updates per [`WINDOW`], later ones are dropped.
+pub const MAX_UPDATES: usize = 5;
+pub const WINDOW: Duration = Duration::from_secs(20);
+
+/// Source of the current time, so the limiter can be driven by a fake clock.
+pub trait Clock {
+    fn now(&self) -> Instant;
+}
+
+#[derive(Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// Keeps activity updates within Discord's rate limit.
+///
+/// Updates which look like the last sent one are skipped. Updates which don't fit into the
+/// window wait until they do, and a newer update replaces a waiting one.
+pub struct UpdateLimiter<T, C = SystemClock> {
+    clock: C,
+    sent: VecDeque<Instant>,
+    last_sent: Option<Value>,
+    pending: Option<(T, Option<Value>)>,
+}
+
+impl<T: Serialize> Default for UpdateLimiter<T> {
+    fn default() -> Self {
+        UpdateLimiter::with_clock(SystemClock)
+    }
+}
+
+impl<T: Serialize, C: Clock> UpdateLimiter<T, C> {
+    pub fn with_clock(clock: C) -> Self {
+        UpdateLimiter {
+            clock,
+            sent: VecDeque::with_capacity(MAX_UPDATES),
+            last_sent: None,
+            pending: None,
+        }
+    }
+
+    /// Queues an update, returning it if it may be sent right away.
+    pub fn submit(&mut self, update: T) -> Option<T> {
+        let value = serde_json::to_value(&update).ok();
+        if value.is_some() && value == self.last_sent {
+            // Whatever was waiting is outdated, and what Discord shows is already up to date.
+            self.pending = None;
+            return None;
+        }
+        self.pending = Some((update, value));
+        self.take_ready()
+    }
+
+    /// When the waiting update may be sent, if there is one.
+    pub fn next_ready(&self) -> Option<Instant> {
+        self.pending.as_ref()?;
+        match self.sent.front() {
+            Some(oldest) if self.sent.len() >= MAX_UPDATES => Some(*oldest + WINDOW),
+            _ => Some(self.clock.now()),
+        }
+    }
+
+    /// Takes the waiting update if it may be sent now.
+    pub fn take_ready(&mut self) -> Option<T> {
+        let now = self.clock.now();
+        while matches!(self.sent.front(), Some(sent) if *sent + WINDOW <= now) {
+            self.sent.pop_front();
+        }
+        if self.sent.len() >= MAX_UPDATES {
+            return None;
+        }
+        let (update, value) = self.pending.take()?;
+        self.sent.push_back(now);
+        self.last_sent = value;
+        Some(update)
+    }
+
+    /// Forgets the waiting and the last sent update, e.g. after the activity was cleared.
+    pub fn reset(&mut self) {
+        self.pending = None;
+        self.last_sent = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, rc::Rc};
+
+    use super::*;
+
+    /// A clock which only moves when the test advances it.
+    #[derive(Clone)]
+    struct FakeClock(Rc<Cell<Instant>>);
+
+    impl FakeClock {
+        fn advance(&self, duration: Duration) {
+            self.0.set(self.0.get() + duration);
+        }
+    }
+
+    impl Clock for FakeClock {
+        fn now(&self) -> Instant {
+            self.0.get()
+        }
+    }
+
+    fn limiter() -> (UpdateLimiter<String, FakeClock>, FakeClock) {
+        let clock = FakeClock(Rc::new(Cell::new(Instant::now())));
+        (UpdateLimiter::with_clock(clock.clone()), clock)
+    }
+
+    /// Sends as many distinct updates as fit into the window.
+    fn fill_window(limiter: &mut UpdateLimiter<String, FakeClock>) {
+        for i in 0..MAX_UPDATES {
+            assert_eq!(limiter.submit(i.to_string()), Some(i.to_string()));
+        }
+    }
+
+    #[test]
+    fn sends_updates_right_away_while_the_window_has_room() {
+        let (mut limiter, clock) = limiter();
+        fill_window(&mut limiter);
+        assert_eq!(limiter.next_ready(), None);
+        assert_eq!(limiter.submit("late".to_owned()), None);
+        assert_eq!(limiter.next_ready(), Some(clock.now() + WINDOW));
+    }
+
+    #[test]
+    fn waits_until_the_oldest_update_leaves_the_window() {
+        let (mut limiter, clock) = limiter();
+        let start = clock.now();
+        assert_eq!(limiter.submit("first".to_owned()), Some("first".to_owned()));
+        clock.advance(Duration::from_secs(10));
+        for i in 1..MAX_UPDATES {
+            assert_eq!(limiter.submit(i.to_string()), Some(i.to_string()));
+        }
+        assert_eq!(limiter.submit("late".to_owned()), None);
+        assert_eq!(limiter.next_ready(), Some(start + WINDOW));
+
+        clock.advance(Duration::from_secs(9));
+        assert_eq!(limiter.take_ready(), None);
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(limiter.take_ready(), Some("late".to_owned()));
+        assert_eq!(limiter.next_ready(), None);
+        // Only the first update left the window, the others still count.
+        assert_eq!(limiter.submit("later".to_owned()), None);
+        assert_eq!(
+            limiter.next_ready(),
+            Some(start + Duration::from_secs(10) + WINDOW)
+        );
+    }
+
+    #[test]
+    fn newer_updates_replace_waiting_ones() {
+        let (mut limiter, clock) = limiter();
+        fill_window(&mut limiter);
+        assert_eq!(limiter.submit("first".to_owned()), None);
+        assert_eq!(limiter.submit("second".to_owned()), None);
+        clock.advance(WINDOW);
+        assert_eq!(limiter.take_ready(), Some("second".to_owned()));
+        assert_eq!(limiter.take_ready(), None);
+    }
+
+    #[test]
+    fn skips_updates_which_match_the_last_sent_one() {
+        let (mut limiter, _) = limiter();
+        assert_eq!(limiter.submit("menu".to_owned()), Some("menu".to_owned()));
+        assert_eq!(limiter.submit("menu".to_owned()), None);
+        assert_eq!(limiter.next_ready(), None);
+        // Duplicates don't use up the window.
+        for i in 1..MAX_UPDATES {
+            assert_eq!(limiter.submit(i.to_string()), Some(i.to_string()));
+        }
+    }
+
+    #[test]
+    fn returning_to_the_sent_update_drops_the_waiting_one() {
+        let (mut limiter, clock) = limiter();
+        fill_window(&mut limiter);
+        let last = (MAX_UPDATES - 1).to_string();
+        assert_eq!(limiter.submit("queue".to_owned()), None);
+        assert_eq!(limiter.submit(last), None);
+        assert_eq!(limiter.next_ready(), None);
+        clock.advance(WINDOW);
+        assert_eq!(limiter.take_ready(), None);
+    }
+
+    #[test]
+    fn reset_forgets_the_last_sent_and_the_waiting_update() {
+        let (mut limiter, clock) = limiter();
+        fill_window(&mut limiter);
+        assert_eq!(limiter.submit("queue".to_owned()), None);
+        limiter.reset();
+        assert_eq!(limiter.next_ready(), None);
+        clock.advance(WINDOW);
+        assert_eq!(limiter.take_ready(), None);
+        // The update Discord showed before the reset is sent again.
+        let last = (MAX_UPDATES - 1).to_string();
+        assert_eq!(limiter.submit(last.clone()), Some(last));
+    }
+}
@@ -1,59 +1,151 @@
+use std::future::pending;
+
 use discord_sdk::{
-    user::User,
-    wheel::{UserState, Wheel},
+    activity::events::ActivityEvent,
+    wheel::{ActivitySpoke, UserSpoke, UserState, Wheel},
     Discord, DiscordApp, Subscriptions,
 };
+use serde_json::{json, Value};
+use tokio::sync::{broadcast::error::RecvError, Mutex};
+use tracing::{debug, info, instrument, warn};
+
+use self::ipc::ActivitySocket;
+use crate::error::Result;
 
 pub mod activity;
+pub mod idle;
+pub mod ipc;
+pub mod join;
+pub mod limiter;
+pub mod template;
+
+pub enum DiscordEvent {
+    Connected,
+    Disconnected,
+    Activity(ActivityEvent),
+}
 
+/// The connection to the local Discord client.
+///
+/// Discord doesn't have to be running, the connection is established in the background and
+/// re-established whenever Discord restarts.
 pub struct DiscordPresence {
     pub discord: Discord,
-    pub user: User,
     pub wheel: Wheel,
-    pub client_id: i64,
+    pub activity_events: ActivitySpoke,
+    user: UserSpoke,
+    /// Sends the activities, as discord-sdk can't send their buttons.
+    activity: Mutex<ActivitySocket>,
+    connected: bool,
 }
 
 impl DiscordPresence {
-    pub async fn new(client_id: i64) -> DiscordPresence {
+    pub fn new(client_id: i64) -> Result<DiscordPresence> {
         let (wheel, handler) = Wheel::new(Box::new(|err| {
-            eprintln!("{}", err);
+            warn!("{}", err);
         }));
-        let mut user = wheel.user();
+        let user = wheel.user();
+        let activity_events = wheel.activity();
         let discord = Discord::new(
             DiscordApp::PlainId(client_id),
             Subscriptions::ALL,
             Box::new(handler),
-        )
-        .expect("unable to create discord client.");
+        )?;
+        info!("waiting for Discord...");
 
-        println!("waiting for handshake...");
-        user.0.changed().await.unwrap();
+        Ok(DiscordPresence {
+            discord,
+            wheel,
+            activity_events,
+            user,
+            activity: Mutex::new(ActivitySocket::new(client_id)),
+            connected: false,
+        })
+    }
 
-        let user = match &*user.0.borrow() {
-            UserState::Connected(user) => user.clone(),
-            UserState::Disconnected(err) => panic!("failed to connect to Discord: {}", err),
-        };
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
 
-        println!("connected to Discord, local user is {}#{:0>4}", user.username, user.discriminator.unwrap());
+    /// Waits for the next activity event or until the connection to Discord is established
+    /// or lost.
+    pub async fn next_event(&mut self) -> DiscordEvent {
+        loop {
+            tokio::select! {
+                changed = self.user.0.changed() => {
+                    if changed.is_err() {
+                        // The handler is gone, so nothing can happen anymore.
+                        return pending().await;
+                    }
+                    if let Some(connected) = self.update_connection() {
+                        return if connected {
+                            DiscordEvent::Connected
+                        } else {
+                            DiscordEvent::Disconnected
+                        };
+                    }
+                }
+                event = self.activity_events.0.recv() => match event {
+                    Ok(event) => return DiscordEvent::Activity(event),
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return pending().await,
+                },
+            }
+        }
+    }
 
-        DiscordPresence {
-            discord,
-            user,
-            wheel,
-            client_id,
+    /// Reads the user state, returning whether Discord is connected if that changed.
+    fn update_connection(&mut self) -> Option<bool> {
+        let connected = match &*self.user.0.borrow() {
+            UserState::Connected(user) => {
+                if !self.connected {
+                    match user.discriminator {
+                        Some(discriminator) => info!(
+                            "connected to Discord, local user is {}#{:0>4}",
+                            user.username, discriminator
+                        ),
+                        None => info!("connected to Discord, local user is {}", user.username),
+                    }
+                }
+                true
+            }
+            UserState::Disconnected(err) => {
+                if self.connected {
+                    warn!("disconnected from Discord: {}", err);
+                }
+                self.activity.get_mut().close();
+                false
+            }
+        };
+        if connected == self.connected {
+            return None;
         }
+        self.connected = connected;
+        Some(connected)
     }
 
-    pub async fn check_connection(&mut self) {
-        let user = self.wheel.user();
-        let state = &*user.0.borrow();
-        if let UserState::Connected(_) = state {
+    #[instrument(skip_all)]
+    pub async fn update_activity(&self, activity: Value) {
+        debug!(%activity, "updating activity");
+        if let Err(e) = self.activity.lock().await.set_activity(activity).await {
+            warn!("unable to update activity: {}", e);
+        }
+    }
+
+    #[instrument(skip_all)]
+    pub async fn clear_activity(&self) {
+        if !self.connected {
             return;
         }
+        let args = json!({ "pid": std::process::id() });
+        if let Err(e) = self.activity.lock().await.set_activity(args).await {
+            warn!("unable to clear activity: {}", e);
+        }
+    }
 
-        let client = DiscordPresence::new(self.client_id).await;
-        self.discord = client.discord;
-        self.user = client.user;
-        self.wheel = client.wheel;
+    pub async fn disconnect(self) {
+        self.clear_activity().await;
+        self.discord.disconnect().await;
+        info!("disconnected from Discord");
     }
 }
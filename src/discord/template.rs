@@ -0,0 +1,552 @@
+use std::{collections::HashMap, fmt::Display};
+
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde::{de::Error, Deserialize, Deserializer};
+
+/// Every placeholder which can be used in a template.
+pub const PLACEHOLDERS: &[&str] = &[
+    "mode",
+    "activity",
+    "map",
+    "ally",
+    "enemy",
+    "party_size",
+    "party_max",
+    "rank",
+    "agent",
+    "custom_name",
+    "custom_team",
+    "name",
+    "tag",
+    "riot_id",
+];
+
+/// Conditions which can be used in `{if ...}` besides the placeholders.
+pub const CONDITIONS: &[&str] = &[
+    "winning",
+    "losing",
+    "tied",
+    "in_party",
+    "has_rounds",
+    "agent_locked",
+];
+
+/// Discord rejects details and state texts longer than this many bytes.
+pub const MAX_LENGTH: usize = 128;
+
+/// Characters which are encoded in values of urls, everything but the unreserved ones.
+const URL_VALUE: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// A piece of activity text which gets filled in from the game state.
+///
+/// Templates support `{placeholder}`s, fallbacks for missing values with
+/// `{placeholder|fallback}`, conditionals with `{if condition}...{else}...{end}` and
+/// `{if not condition}`, and literal braces with `{{` and `}}`.
+#[derive(Debug, Clone)]
+pub struct Template {
+    nodes: Vec<Node>,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Text(String),
+    Placeholder {
+        name: String,
+        fallback: Option<String>,
+    },
+    If {
+        condition: String,
+        negated: bool,
+        then: Vec<Node>,
+        otherwise: Vec<Node>,
+    },
+}
+
+/// The values and conditions a template is rendered with.
+///
+/// Placeholders without a value count as missing and render their fallback.
+#[derive(Debug, Default)]
+pub struct Context {
+    pub values: HashMap<&'static str, String>,
+    pub flags: HashMap<&'static str, bool>,
+}
+
+impl Context {
+    pub fn value(&mut self, name: &'static str, value: impl Into<String>) {
+        let value = value.into();
+        if !value.is_empty() {
+            self.values.insert(name, value);
+        }
+    }
+
+    pub fn flag(&mut self, name: &'static str, value: bool) {
+        self.flags.insert(name, value);
+    }
+
+    /// The same context with percent-encoded values, to render urls.
+    pub fn url_encoded(&self) -> Context {
+        let values = self
+            .values
+            .iter()
+            .map(|(name, value)| (*name, utf8_percent_encode(value, URL_VALUE).to_string()))
+            .collect();
+        Context {
+            values,
+            flags: self.flags.clone(),
+        }
+    }
+
+    fn is_true(&self, condition: &str) -> bool {
+        match self.flags.get(condition) {
+            Some(flag) => *flag,
+            None => self.values.contains_key(condition),
+        }
+    }
+}
+
+impl Template {
+    pub fn parse(template: &str) -> Result<Template, TemplateError> {
+        let tokens = tokenize(template)?;
+        let mut tokens = tokens.into_iter();
+        let (nodes, terminator) = parse_nodes(&mut tokens)?;
+        match terminator {
+            None => Ok(Template { nodes }),
+            Some(Terminator::Else) => Err(TemplateError::Unexpected("else")),
+            Some(Terminator::End) => Err(TemplateError::Unexpected("end")),
+        }
+    }
+
+    /// Renders the template, clamped to Discord's [`MAX_LENGTH`].
+    pub fn render(&self, context: &Context) -> String {
+        clamp(&self.render_unclamped(context), MAX_LENGTH)
+    }
+
+    /// Renders the template at full length, for texts which can't be shortened, like urls.
+    pub fn render_unclamped(&self, context: &Context) -> String {
+        let mut output = String::new();
+        render_nodes(&self.nodes, context, &mut output);
+        output.trim().to_owned()
+    }
+}
+
+/// Shortens `text` to at most `max` bytes, marking the cut with an ellipsis.
+pub fn clamp(text: &str, max: usize) -> String {
+    if text.len() <= max {
+        return text.to_owned();
+    }
+    let ellipsis = '…';
+    let mut end = max - ellipsis.len_utf8();
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}{}", text[..end].trim_end(), ellipsis)
+}
+
+fn render_nodes(nodes: &[Node], context: &Context, output: &mut String) {
+    for node in nodes {
+        match node {
+            Node::Text(text) => output.push_str(text),
+            Node::Placeholder { name, fallback } => match context.values.get(name.as_str()) {
+                Some(value) => output.push_str(value),
+                None => output.push_str(fallback.as_deref().unwrap_or("")),
+            },
+            Node::If {
+                condition,
+                negated,
+                then,
+                otherwise,
+            } => {
+                if context.is_true(condition) != *negated {
+                    render_nodes(then, context, output);
+                } else {
+                    render_nodes(otherwise, context, output);
+                }
+            }
+        }
+    }
+}
+
+enum Token {
+    Text(String),
+    Tag(String),
+}
+
+enum Terminator {
+    Else,
+    End,
+}
+
+fn tokenize(template: &str) -> Result<Vec<Token>, TemplateError> {
+    let mut tokens = Vec::new();
+    let mut text = String::new();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                text.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                text.push('}');
+            }
+            '{' => {
+                let mut tag = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => tag.push(c),
+                        None => return Err(TemplateError::Unclosed),
+                    }
+                }
+                if !text.is_empty() {
+                    tokens.push(Token::Text(std::mem::take(&mut text)));
+                }
+                tokens.push(Token::Tag(tag));
+            }
+            '}' => return Err(TemplateError::Unopened),
+            c => text.push(c),
+        }
+    }
+    if !text.is_empty() {
+        tokens.push(Token::Text(text));
+    }
+    Ok(tokens)
+}
+
+fn parse_nodes(
+    tokens: &mut impl Iterator<Item = Token>,
+) -> Result<(Vec<Node>, Option<Terminator>), TemplateError> {
+    let mut nodes = Vec::new();
+    while let Some(token) = tokens.next() {
+        let tag = match token {
+            Token::Text(text) => {
+                nodes.push(Node::Text(text));
+                continue;
+            }
+            Token::Tag(tag) => tag,
+        };
+        let tag = tag.trim();
+        if tag == "else" {
+            return Ok((nodes, Some(Terminator::Else)));
+        }
+        if tag == "end" {
+            return Ok((nodes, Some(Terminator::End)));
+        }
+        if let Some(condition) = tag.strip_prefix("if ") {
+            nodes.push(parse_if(condition.trim(), tokens)?);
+            continue;
+        }
+        let (name, fallback) = match tag.split_once('|') {
+            Some((name, fallback)) => (name.trim(), Some(fallback.to_owned())),
+            None => (tag, None),
+        };
+        if !PLACEHOLDERS.contains(&name) {
+            return Err(TemplateError::UnknownPlaceholder(name.to_owned()));
+        }
+        nodes.push(Node::Placeholder {
+            name: name.to_owned(),
+            fallback,
+        });
+    }
+    Ok((nodes, None))
+}
+
+fn parse_if(
+    condition: &str,
+    tokens: &mut impl Iterator<Item = Token>,
+) -> Result<Node, TemplateError> {
+    let (condition, negated) = match condition.strip_prefix("not ") {
+        Some(condition) => (condition.trim(), true),
+        None => (condition, false),
+    };
+    if !CONDITIONS.contains(&condition) && !PLACEHOLDERS.contains(&condition) {
+        return Err(TemplateError::UnknownCondition(condition.to_owned()));
+    }
+    let (then, terminator) = parse_nodes(tokens)?;
+    let otherwise = match terminator {
+        Some(Terminator::End) => Vec::new(),
+        Some(Terminator::Else) => match parse_nodes(tokens)? {
+            (otherwise, Some(Terminator::End)) => otherwise,
+            (_, Some(Terminator::Else)) => return Err(TemplateError::Unexpected("else")),
+            (_, None) => return Err(TemplateError::MissingEnd),
+        },
+        None => return Err(TemplateError::MissingEnd),
+    };
+    Ok(Node::If {
+        condition: condition.to_owned(),
+        negated,
+        then,
+        otherwise,
+    })
+}
+
+impl<'de> Deserialize<'de> for Template {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let template = String::deserialize(deserializer)?;
+        Template::parse(&template).map_err(D::Error::custom)
+    }
+}
+
+#[derive(Debug)]
+pub enum TemplateError {
+    Unclosed,
+    Unopened,
+    MissingEnd,
+    Unexpected(&'static str),
+    UnknownPlaceholder(String),
+    UnknownCondition(String),
+}
+
+impl Display for TemplateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TemplateError::Unclosed => f.write_str("placeholder is missing a closing }"),
+            TemplateError::Unopened => f.write_str("unexpected }, use }} for a literal brace"),
+            TemplateError::MissingEnd => f.write_str("{if} is missing its {end}"),
+            TemplateError::Unexpected(tag) => {
+                f.write_fmt(format_args!("{{{}}} without a matching {{if}}", tag))
+            }
+            TemplateError::UnknownPlaceholder(name) => f.write_fmt(format_args!(
+                "unknown placeholder {{{}}}, expected one of {}",
+                name,
+                PLACEHOLDERS.join(", ")
+            )),
+            TemplateError::UnknownCondition(name) => f.write_fmt(format_args!(
+                "unknown condition {}, expected one of {}, {}",
+                name,
+                CONDITIONS.join(", "),
+                PLACEHOLDERS.join(", ")
+            )),
+        }
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(template: &str, context: &Context) -> String {
+        Template::parse(template).unwrap().render(context)
+    }
+
+    fn context() -> Context {
+        let mut context = Context::default();
+        context.value("map", "Ascent");
+        context.value("ally", "7");
+        context.value("enemy", "5");
+        context.flag("winning", true);
+        context.flag("losing", false);
+        context
+    }
+
+    #[test]
+    fn renders_placeholders() {
+        assert_eq!(
+            render("{ally} - {enemy} on {map}", &context()),
+            "7 - 5 on Ascent"
+        );
+        assert_eq!(render("{ map }", &context()), "Ascent");
+    }
+
+    #[test]
+    fn missing_placeholders_render_empty_and_are_trimmed() {
+        assert_eq!(render("{agent} on {map}", &context()), "on Ascent");
+        assert_eq!(render("{agent}", &context()), "");
+    }
+
+    #[test]
+    fn empty_values_count_as_missing() {
+        let mut context = context();
+        context.value("agent", "");
+        assert_eq!(render("{agent|Nobody}", &context), "Nobody");
+    }
+
+    #[test]
+    fn renders_fallbacks_of_missing_placeholders() {
+        assert_eq!(
+            render("{agent|Somebody} on {map|a map}", &context()),
+            "Somebody on Ascent"
+        );
+        assert_eq!(render("{agent|}", &context()), "");
+    }
+
+    #[test]
+    fn renders_conditionals() {
+        let template = "{if winning}Winning{else}Not winning{end}";
+        assert_eq!(render(template, &context()), "Winning");
+        let mut losing = context();
+        losing.flag("winning", false);
+        assert_eq!(render(template, &losing), "Not winning");
+        assert_eq!(
+            render("Playing{if losing} badly{end}", &context()),
+            "Playing"
+        );
+    }
+
+    #[test]
+    fn renders_negated_conditionals() {
+        assert_eq!(render("{if not losing}Fine{end}", &context()), "Fine");
+        assert_eq!(
+            render("{if not winning}Bad{else}Good{end}", &context()),
+            "Good"
+        );
+    }
+
+    #[test]
+    fn placeholders_are_conditions() {
+        assert_eq!(render("{if map}on {map}{end}", &context()), "on Ascent");
+        assert_eq!(render("{if agent}as {agent}{end}", &context()), "");
+        assert_eq!(
+            render("{if not agent}No agent{end}", &context()),
+            "No agent"
+        );
+    }
+
+    #[test]
+    fn unset_flags_are_false() {
+        assert_eq!(
+            render("{if tied}Tied{else}Not tied{end}", &context()),
+            "Not tied"
+        );
+    }
+
+    #[test]
+    fn renders_nested_conditionals() {
+        let template = "{if map}{if winning}Winning{else}Losing{end} on {map}{end}";
+        assert_eq!(render(template, &context()), "Winning on Ascent");
+        assert_eq!(render(template, &Context::default()), "");
+    }
+
+    #[test]
+    fn renders_escaped_braces() {
+        assert_eq!(render("{{map}} {map}", &context()), "{map} Ascent");
+        assert_eq!(render("}}{{", &context()), "}{");
+    }
+
+    #[test]
+    fn rejects_invalid_templates() {
+        let error = |template| Template::parse(template).unwrap_err();
+        assert!(matches!(error("{map"), TemplateError::Unclosed));
+        assert!(matches!(error("map}"), TemplateError::Unopened));
+        assert!(matches!(
+            error("{if winning}Winning"),
+            TemplateError::MissingEnd
+        ));
+        assert!(matches!(
+            error("{if winning}Winning{else}Losing"),
+            TemplateError::MissingEnd
+        ));
+        assert!(matches!(error("{else}"), TemplateError::Unexpected("else")));
+        assert!(matches!(
+            error("{if winning}a{else}b{else}c{end}"),
+            TemplateError::Unexpected("else")
+        ));
+        assert!(matches!(error("{end}"), TemplateError::Unexpected("end")));
+        assert!(matches!(
+            error("{if winning}a{end}{end}"),
+            TemplateError::Unexpected("end")
+        ));
+        assert!(
+            matches!(error("{score}"), TemplateError::UnknownPlaceholder(name) if name == "score")
+        );
+        assert!(
+            matches!(error("{score|0}"), TemplateError::UnknownPlaceholder(name) if name == "score")
+        );
+        assert!(
+            matches!(error("{if happy}:){end}"), TemplateError::UnknownCondition(name) if name == "happy")
+        );
+        assert!(
+            matches!(error("{if not happy}:({end}"), TemplateError::UnknownCondition(name) if name == "happy")
+        );
+    }
+
+    #[test]
+    fn describes_errors() {
+        let error = Template::parse("{score}").unwrap_err().to_string();
+        assert!(error.starts_with("unknown placeholder {score}, expected one of mode, "));
+        let error = Template::parse("{end}").unwrap_err().to_string();
+        assert_eq!(error, "{end} without a matching {if}");
+    }
+
+    #[test]
+    fn deserializes_and_validates_templates() {
+        #[derive(Deserialize, Debug)]
+        struct Text {
+            details: Template,
+        }
+        let text: Text = toml::from_str("details = \"On {map}\"").unwrap();
+        assert_eq!(text.details.render(&context()), "On Ascent");
+        let error = toml::from_str::<Text>("details = \"On {place}\"").unwrap_err();
+        assert!(error.to_string().contains("unknown placeholder {place}"));
+    }
+
+    #[test]
+    fn encodes_values_of_urls() {
+        let mut context = Context::default();
+        context.value("name", "Näme with space");
+        context.value("riot_id", "a/b?c#TAG");
+        context.flag("winning", true);
+        let template =
+            Template::parse("https://example.com/{name}%23{riot_id}{if winning}?w{end}").unwrap();
+        assert_eq!(
+            template.render_unclamped(&context.url_encoded()),
+            "https://example.com/N%C3%A4me%20with%20space%23a%2Fb%3Fc%23TAG?w"
+        );
+    }
+
+    #[test]
+    fn render_unclamped_keeps_long_text() {
+        let mut context = Context::default();
+        context.value("custom_name", "a".repeat(300));
+        let template = Template::parse(" {custom_name} ").unwrap();
+        assert_eq!(template.render_unclamped(&context), "a".repeat(300));
+    }
+
+    #[test]
+    fn clamp_keeps_short_text() {
+        assert_eq!(clamp("Ascent", MAX_LENGTH), "Ascent");
+        let text = "a".repeat(MAX_LENGTH);
+        assert_eq!(clamp(&text, MAX_LENGTH), text);
+    }
+
+    #[test]
+    fn clamp_cuts_long_text_with_an_ellipsis() {
+        let clamped = clamp(&"a".repeat(MAX_LENGTH + 1), MAX_LENGTH);
+        assert_eq!(clamped.len(), MAX_LENGTH);
+        assert!(clamped.ends_with('…'));
+        // Whitespace in front of the cut is dropped.
+        let text = format!("{} {}", "a".repeat(124), "b".repeat(10));
+        assert_eq!(clamp(&text, MAX_LENGTH), format!("{}…", "a".repeat(124)));
+    }
+
+    #[test]
+    fn clamp_cuts_at_char_boundaries() {
+        // 'é' takes two bytes, so the cut at 125 bytes would split one.
+        let clamped = clamp(&"é".repeat(100), MAX_LENGTH);
+        assert_eq!(clamped, format!("{}…", "é".repeat(62)));
+        assert!(clamped.len() <= MAX_LENGTH);
+        // '🎯' takes four bytes.
+        let clamped = clamp(&"🎯".repeat(40), MAX_LENGTH);
+        assert_eq!(clamped, format!("{}…", "🎯".repeat(31)));
+        assert!(clamped.len() <= MAX_LENGTH);
+    }
+
+    #[test]
+    fn render_clamps_to_discord_limit() {
+        let mut context = Context::default();
+        context.value("custom_name", "é".repeat(100));
+        let rendered = render("{custom_name}", &context);
+        assert!(rendered.len() <= MAX_LENGTH);
+        assert!(rendered.ends_with('…'));
+    }
+}
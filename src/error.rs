@@ -0,0 +1,139 @@
+use std::fmt::{self, Display};
+
+use tokio_tungstenite::tungstenite;
+
+use crate::{
+    config::ConfigError,
+    lockfile::{LockfileError, RiotDirNotFoundError},
+    valorant::table::TableError,
+};
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Everything that can go wrong between the Riot Client and Discord.
+pub enum Error {
+    Lockfile(LockfileError),
+    RiotDirNotFound(RiotDirNotFoundError),
+    /// A request to the local Riot Client or the game servers failed.
+    RiotHttp(reqwest::Error),
+    /// The Riot Client answered, but not with what we need.
+    RiotClient(String),
+    /// Boxed, as it is by far the largest of these errors.
+    Websocket(Box<tungstenite::Error>),
+    Discord(discord_sdk::Error),
+    /// The connection which sends the activity to Discord failed.
+    DiscordIpc(String),
+    /// Discord refused a command, e.g. an activity it considers invalid.
+    DiscordRejected(String),
+    /// None of the Riot friends is in the party a Discord join secret was made for.
+    InvalidJoinSecret(String),
+    Config(ConfigError),
+    /// The map table or the queue registry can't be loaded.
+    Table(TableError),
+    /// The command line arguments are invalid.
+    Usage(String),
+    /// Logging can't be set up as configured.
+    Logging(String),
+}
+
+impl Error {
+    /// Whether trying again later can help. Other errors need the user to step in.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::Lockfile(_)
+            | Error::RiotHttp(_)
+            | Error::RiotClient(_)
+            | Error::Websocket(_)
+            | Error::Discord(_)
+            | Error::DiscordIpc(_) => true,
+            Error::RiotDirNotFound(_)
+            | Error::DiscordRejected(_)
+            | Error::InvalidJoinSecret(_)
+            | Error::Config(_)
+            | Error::Table(_)
+            | Error::Usage(_)
+            | Error::Logging(_) => false,
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Lockfile(e) => e.fmt(f),
+            Error::RiotDirNotFound(e) => e.fmt(f),
+            Error::RiotHttp(e) => f.write_fmt(format_args!("Riot request failed: {}", e)),
+            Error::RiotClient(message) => f.write_fmt(format_args!("Riot Client: {}", message)),
+            Error::Websocket(e) => f.write_fmt(format_args!("websocket error: {}", e)),
+            Error::Discord(e) => f.write_fmt(format_args!("Discord error: {}", e)),
+            Error::DiscordIpc(message) => f.write_fmt(format_args!("Discord IPC: {}", message)),
+            Error::DiscordRejected(message) => {
+                f.write_fmt(format_args!("Discord rejected the command: {}", message))
+            }
+            Error::InvalidJoinSecret(secret) => f.write_fmt(format_args!(
+                "join secret {} doesn't belong to the party of a Riot friend",
+                secret
+            )),
+            Error::Config(e) => e.fmt(f),
+            Error::Table(e) => e.fmt(f),
+            Error::Usage(message) => f.write_fmt(format_args!(
+                "{}\nusage: {} [--config <path>] [--riot-dir <path>]",
+                message,
+                env!("CARGO_PKG_NAME")
+            )),
+            Error::Logging(message) => {
+                f.write_fmt(format_args!("unable to set up logging: {}", message))
+            }
+        }
+    }
+}
+
+/// `main` returns this error, so its debug output is what the user sees when the app fails.
+impl fmt::Debug for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Lockfile(e) => Some(e),
+            Error::RiotDirNotFound(e) => Some(e),
+            Error::RiotHttp(e) => Some(e),
+            Error::Websocket(e) => Some(e.as_ref()),
+            Error::Discord(e) => Some(e),
+            Error::Config(e) => Some(e),
+            Error::Table(e) => Some(e),
+            Error::RiotClient(_)
+            | Error::DiscordIpc(_)
+            | Error::DiscordRejected(_)
+            | Error::InvalidJoinSecret(_)
+            | Error::Usage(_)
+            | Error::Logging(_) => None,
+        }
+    }
+}
+
+macro_rules! from {
+    ($variant:ident, $error:ty) => {
+        impl From<$error> for Error {
+            fn from(e: $error) -> Self {
+                Error::$variant(e)
+            }
+        }
+    };
+}
+
+from!(Lockfile, LockfileError);
+from!(RiotDirNotFound, RiotDirNotFoundError);
+from!(RiotHttp, reqwest::Error);
+from!(Discord, discord_sdk::Error);
+from!(Config, ConfigError);
+from!(Table, TableError);
+
+impl From<tungstenite::Error> for Error {
+    fn from(e: tungstenite::Error) -> Self {
+        Error::Websocket(Box::new(e))
+    }
+}
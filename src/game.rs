@@ -1,51 +1,55 @@
-use std::{
-    path::Path,
-    sync::mpsc::{channel, Sender},
-    thread,
-    time::Duration,
-};
+use std::{path::Path, sync::mpsc::channel, thread, time::Duration};
 
 use notify::{watcher, DebouncedEvent, RecursiveMode, Watcher};
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::error;
 
-pub fn watch(sender: Sender<GameMessage>, game_dir: &Path) {
+use crate::lockfile::{LockfileError, LockfileErrorKind};
+
+pub fn watch(sender: UnboundedSender<GameMessage>, game_dir: &Path) -> Result<(), LockfileError> {
     let dir = game_dir.to_path_buf();
     let file = dir.join("lockfile");
+    let error = |e| LockfileError {
+        path: dir.clone(),
+        kind: LockfileErrorKind::Watch(e),
+    };
+    let (tx, rx) = channel();
+    let mut watcher = watcher(tx, Duration::from_millis(300)).map_err(error)?;
+    watcher
+        .watch(&dir, RecursiveMode::NonRecursive)
+        .map_err(error)?;
     if file.exists() {
         // Game is already running.
-        sender.send(GameMessage::GameStarted).expect("Unable to send message");
+        let _ = sender.send(GameMessage::GameStarted);
     }
 
     thread::spawn(move || {
-        let (tx, rx) = channel();
-        let mut watcher = watcher(tx, Duration::from_millis(300)).unwrap();
-        watcher.watch(dir, RecursiveMode::NonRecursive).unwrap();
+        // The watcher stops watching once it is dropped.
+        let _watcher = watcher;
         loop {
             let event = match rx.recv() {
                 Ok(event) => event,
                 Err(e) => {
-                    println!("error: {}", e);
-                    continue;
+                    error!("Lockfile watcher stopped: {}", e);
+                    return;
                 }
             };
-            match event {
-                DebouncedEvent::Create(path) => {
-                    if path.ends_with("lockfile") {
-                        sender
-                            .send(GameMessage::GameStarted)
-                            .expect("Unable to send message");
-                    }
+            let message = match event {
+                DebouncedEvent::Create(path) if path.ends_with("lockfile") => {
+                    GameMessage::GameStarted
                 }
-                DebouncedEvent::NoticeRemove(path) => {
-                    if path.ends_with("lockfile") {
-                        sender
-                            .send(GameMessage::GameStopped)
-                            .expect("Unable to send message");
-                    }
+                DebouncedEvent::NoticeRemove(path) if path.ends_with("lockfile") => {
+                    GameMessage::GameStopped
                 }
                 _ => continue,
             };
+            // The receiver only goes away when the app shuts down.
+            if sender.send(message).is_err() {
+                return;
+            }
         }
     });
+    Ok(())
 }
 
 #[derive(Debug)]
@@ -1,10 +1,17 @@
 use std::env;
 use std::error::Error;
 use std::fmt::Display;
-use std::path::Path;
+use std::fs::read_dir;
+use std::io;
+use std::path::{Path, PathBuf};
 
-use anyhow::Result;
 use tokio::fs;
+use tracing::instrument;
+
+/// Environment variable which can point directly at the Riot Client config directory.
+pub const RIOT_DIR_ENV: &str = "VALORANT_RPC_RIOT_DIR";
+
+const RIOT_CONFIG_PATH: &str = "Riot Games/Riot Client/Config";
 
 #[derive(Debug)]
 pub struct RiotCredentials {
@@ -16,25 +23,35 @@ pub struct RiotCredentials {
 }
 
 macro_rules! next {
-    ($lockfile_values:expr, $name:expr) => {
+    ($lockfile_values:expr, $name:expr, $error:expr) => {
         $lockfile_values
             .next()
-            .ok_or_else(|| FieldMissingError::new($name))?
-            .parse()?
+            .ok_or_else(|| {
+                $error(LockfileErrorKind::MissingField(FieldMissingError::new(
+                    $name,
+                )))
+            })?
+            .parse()
+            .map_err(|_| $error(LockfileErrorKind::InvalidField($name)))?
     };
 }
 
-pub async fn get_lockfile_credentials() -> Result<RiotCredentials> {
-    let local_app_data = env::var("LOCALAPPDATA")?;
-    let local_app_data = Path::new(&local_app_data);
-    let lockfile = local_app_data.join("Riot Games/Riot Client/Config/lockfile");
-    let lockfile_content = fs::read_to_string(&lockfile).await?;
+#[instrument(skip_all, fields(riot_dir = %riot_dir.display()))]
+pub async fn get_lockfile_credentials(riot_dir: &Path) -> Result<RiotCredentials, LockfileError> {
+    let lockfile = riot_dir.join("lockfile");
+    let error = |kind| LockfileError {
+        path: lockfile.clone(),
+        kind,
+    };
+    let lockfile_content = fs::read_to_string(&lockfile)
+        .await
+        .map_err(|e| error(LockfileErrorKind::Io(e)))?;
     let mut lockfile_values = lockfile_content.split(':');
-    let name: String = next!(lockfile_values, "name");
-    let pid: u32 = next!(lockfile_values, "pid");
-    let port: u32 = next!(lockfile_values, "port");
-    let password = next!(lockfile_values, "password");
-    let protocol = next!(lockfile_values, "protocol");
+    let name: String = next!(lockfile_values, "name", error);
+    let pid: u32 = next!(lockfile_values, "pid", error);
+    let port: u32 = next!(lockfile_values, "port", error);
+    let password = next!(lockfile_values, "password", error);
+    let protocol = next!(lockfile_values, "protocol", error);
     Ok(RiotCredentials {
         name,
         pid,
@@ -44,8 +61,180 @@ pub async fn get_lockfile_credentials() -> Result<RiotCredentials> {
     })
 }
 
+/// Finds the Riot Client config directory which contains the lockfile.
+///
+/// The explicit overrides are checked first, followed by [`RIOT_DIR_ENV`], `LOCALAPPDATA`
+/// and finally the users of any Wine or Proton prefix we know about.
+pub fn find_riot_dir(overrides: &[PathBuf]) -> Result<PathBuf, RiotDirNotFoundError> {
+    search_riot_dir(overrides, &SearchEnv::from_env())
+}
+
+/// The environment variables the search for the Riot Client directory depends on.
+#[derive(Debug, Default)]
+struct SearchEnv {
+    riot_dir: Option<PathBuf>,
+    local_app_data: Option<PathBuf>,
+    wine_prefix: Option<PathBuf>,
+    home: Option<PathBuf>,
+}
+
+impl SearchEnv {
+    fn from_env() -> SearchEnv {
+        SearchEnv {
+            riot_dir: env::var_os(RIOT_DIR_ENV).map(PathBuf::from),
+            local_app_data: env::var_os("LOCALAPPDATA").map(PathBuf::from),
+            wine_prefix: env::var_os("WINEPREFIX").map(PathBuf::from),
+            home: env::var_os("HOME").map(PathBuf::from),
+        }
+    }
+}
+
+enum Candidate {
+    /// A directory which may be the Riot Client directory.
+    Dir(PathBuf),
+    /// Where the directory would be in a Wine prefix without users. It isn't checked, only
+    /// listed so the error shows the prefix was searched.
+    NoUsers(PathBuf),
+}
+
+fn search_riot_dir(
+    overrides: &[PathBuf],
+    env: &SearchEnv,
+) -> Result<PathBuf, RiotDirNotFoundError> {
+    let mut tried = Vec::new();
+    for candidate in candidates(overrides, env) {
+        match candidate {
+            Candidate::Dir(dir) if dir.is_dir() => return Ok(dir),
+            Candidate::Dir(dir) | Candidate::NoUsers(dir) => tried.push(dir),
+        }
+    }
+    Err(RiotDirNotFoundError { tried })
+}
+
+fn candidates(overrides: &[PathBuf], env: &SearchEnv) -> Vec<Candidate> {
+    let mut candidates: Vec<Candidate> = overrides.iter().cloned().map(Candidate::Dir).collect();
+    candidates.extend(env.riot_dir.clone().map(Candidate::Dir));
+    if let Some(local_app_data) = &env.local_app_data {
+        candidates.push(Candidate::Dir(local_app_data.join(RIOT_CONFIG_PATH)));
+    }
+    for prefix in wine_prefixes(env) {
+        let users = subdirectories(&prefix.join("drive_c/users"));
+        if users.is_empty() {
+            candidates.push(Candidate::NoUsers(
+                prefix
+                    .join("drive_c/users/*/AppData/Local")
+                    .join(RIOT_CONFIG_PATH),
+            ));
+        }
+        candidates.extend(
+            users
+                .into_iter()
+                .map(|user| Candidate::Dir(user.join("AppData/Local").join(RIOT_CONFIG_PATH))),
+        );
+    }
+    candidates
+}
+
+fn wine_prefixes(env: &SearchEnv) -> Vec<PathBuf> {
+    let mut prefixes: Vec<PathBuf> = env.wine_prefix.iter().cloned().collect();
+    let home = match &env.home {
+        Some(home) => home,
+        None => return prefixes,
+    };
+    prefixes.push(home.join(".wine"));
+    // Lutris installs every game into its own prefix below ~/Games.
+    prefixes.extend(subdirectories(&home.join("Games")));
+    for steam in [".steam/steam", ".local/share/Steam"] {
+        let compatdata = home.join(steam).join("steamapps/compatdata");
+        prefixes.extend(
+            subdirectories(&compatdata)
+                .into_iter()
+                .map(|app| app.join("pfx")),
+        );
+    }
+    prefixes
+}
+
+fn subdirectories(dir: &Path) -> Vec<PathBuf> {
+    let entries = match read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    let mut dirs: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .collect();
+    dirs.sort();
+    dirs
+}
+
 #[derive(Debug)]
-struct FieldMissingError {
+pub struct RiotDirNotFoundError {
+    pub tried: Vec<PathBuf>,
+}
+
+impl Display for RiotDirNotFoundError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("unable to find the Riot Client config directory")?;
+        if self.tried.is_empty() {
+            return f.write_fmt(format_args!(", set {} or pass --riot-dir", RIOT_DIR_ENV));
+        }
+        f.write_str(", tried:")?;
+        for path in &self.tried {
+            f.write_fmt(format_args!("\n  {}", path.display()))?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for RiotDirNotFoundError {}
+
+#[derive(Debug)]
+pub struct LockfileError {
+    pub path: PathBuf,
+    pub kind: LockfileErrorKind,
+}
+
+#[derive(Debug)]
+pub enum LockfileErrorKind {
+    Io(io::Error),
+    Watch(notify::Error),
+    MissingField(FieldMissingError),
+    InvalidField(&'static str),
+}
+
+impl Display for LockfileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.kind {
+            LockfileErrorKind::Io(e) => f.write_fmt(format_args!(
+                "unable to read {}: {}",
+                self.path.display(),
+                e
+            )),
+            LockfileErrorKind::Watch(e) => f.write_fmt(format_args!(
+                "unable to watch {}: {}",
+                self.path.display(),
+                e
+            )),
+            LockfileErrorKind::MissingField(e) => f.write_fmt(format_args!(
+                "invalid lockfile {}: {}",
+                self.path.display(),
+                e
+            )),
+            LockfileErrorKind::InvalidField(field) => f.write_fmt(format_args!(
+                "invalid lockfile {}: field {} is invalid",
+                self.path.display(),
+                field
+            )),
+        }
+    }
+}
+
+impl Error for LockfileError {}
+
+#[derive(Debug)]
+pub struct FieldMissingError {
     field: String,
 }
 
@@ -64,3 +253,98 @@ impl Display for FieldMissingError {
 }
 
 impl Error for FieldMissingError {}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::create_dir_all;
+
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn riot_dir_in(root: &Path, user_dir: &str) -> PathBuf {
+        let dir = root.join(user_dir).join(RIOT_CONFIG_PATH);
+        create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn overrides_take_precedence() {
+        let root = TempDir::new().unwrap();
+        let override_dir = root.path().join("override");
+        create_dir_all(&override_dir).unwrap();
+        let env = SearchEnv {
+            riot_dir: Some(root.path().to_path_buf()),
+            local_app_data: Some(
+                riot_dir_in(root.path(), "local")
+                    .parent()
+                    .unwrap()
+                    .to_owned(),
+            ),
+            ..SearchEnv::default()
+        };
+        let missing = root.path().join("missing");
+        let found = search_riot_dir(&[missing, override_dir.clone()], &env).unwrap();
+        assert_eq!(found, override_dir);
+        // Without overrides, the environment variable comes first.
+        assert_eq!(search_riot_dir(&[], &env).unwrap(), root.path());
+    }
+
+    #[test]
+    fn finds_local_app_data() {
+        let root = TempDir::new().unwrap();
+        let dir = riot_dir_in(root.path(), "AppData/Local");
+        let env = SearchEnv {
+            riot_dir: Some(root.path().join("missing")),
+            local_app_data: Some(root.path().join("AppData/Local")),
+            ..SearchEnv::default()
+        };
+        assert_eq!(search_riot_dir(&[], &env).unwrap(), dir);
+    }
+
+    #[test]
+    fn finds_users_of_wine_prefixes() {
+        let home = TempDir::new().unwrap();
+        let lutris = riot_dir_in(
+            home.path(),
+            "Games/riot-games/drive_c/users/player/AppData/Local",
+        );
+        let proton = riot_dir_in(
+            home.path(),
+            ".steam/steam/steamapps/compatdata/1234/pfx/drive_c/users/steamuser/AppData/Local",
+        );
+        let env = SearchEnv {
+            home: Some(home.path().to_path_buf()),
+            ..SearchEnv::default()
+        };
+        assert_eq!(search_riot_dir(&[], &env).unwrap(), lutris);
+        std::fs::remove_dir_all(home.path().join("Games")).unwrap();
+        assert_eq!(search_riot_dir(&[], &env).unwrap(), proton);
+    }
+
+    #[test]
+    fn lists_every_searched_location() {
+        let home = TempDir::new().unwrap();
+        let prefix = home.path().join("prefix");
+        create_dir_all(prefix.join("drive_c/users/player")).unwrap();
+        let env = SearchEnv {
+            wine_prefix: Some(prefix.clone()),
+            home: Some(home.path().to_path_buf()),
+            ..SearchEnv::default()
+        };
+        let error = search_riot_dir(&[home.path().join("override")], &env).unwrap_err();
+        assert_eq!(
+            error.tried,
+            [
+                home.path().join("override"),
+                prefix
+                    .join("drive_c/users/player/AppData/Local")
+                    .join(RIOT_CONFIG_PATH),
+                // ~/.wine has no users, so only a placeholder is listed.
+                home.path()
+                    .join(".wine/drive_c/users/*/AppData/Local")
+                    .join(RIOT_CONFIG_PATH),
+            ]
+        );
+    }
+}
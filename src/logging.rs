@@ -0,0 +1,77 @@
+use std::{fs, path::PathBuf};
+
+use tracing_appender::{
+    non_blocking::WorkerGuard,
+    rolling::{RollingFileAppender, Rotation},
+};
+use tracing_subscriber::{fmt, prelude::*, EnvFilter};
+
+use crate::{
+    config::LogConfig,
+    error::{Error, Result},
+};
+
+/// Number of daily log files which are kept around.
+const MAX_LOG_FILES: usize = 7;
+
+/// Sets up logging to stdout and, if enabled, to daily rotating log files.
+///
+/// The returned guard flushes the log file when dropped, so it has to live until the app exits.
+pub fn init(config: &LogConfig) -> Result<Option<WorkerGuard>> {
+    // RUST_LOG takes precedence, so the level can be raised without touching the config.
+    let filter = EnvFilter::try_from_default_env()
+        .or_else(|_| EnvFilter::try_new(&config.level))
+        .map_err(|e| Error::Logging(format!("invalid log level {}: {}", config.level, e)))?;
+    let stdout = if config.json {
+        fmt::layer().json().boxed()
+    } else {
+        fmt::layer().boxed()
+    };
+    let (file, guard) = match log_dir(config).filter(|_| config.file) {
+        Some(dir) => {
+            fs::create_dir_all(&dir).map_err(|e| {
+                Error::Logging(format!(
+                    "unable to create log directory {}: {}",
+                    dir.display(),
+                    e
+                ))
+            })?;
+            let appender = RollingFileAppender::builder()
+                .rotation(Rotation::DAILY)
+                .filename_prefix(env!("CARGO_PKG_NAME"))
+                .filename_suffix("log")
+                .max_log_files(MAX_LOG_FILES)
+                .build(&dir)
+                .map_err(|e| {
+                    Error::Logging(format!(
+                        "unable to create log files in {}: {}",
+                        dir.display(),
+                        e
+                    ))
+                })?;
+            let (writer, guard) = tracing_appender::non_blocking(appender);
+            let layer = fmt::layer().with_writer(writer).with_ansi(false);
+            let layer = if config.json {
+                layer.json().boxed()
+            } else {
+                layer.boxed()
+            };
+            (Some(layer), Some(guard))
+        }
+        None => (None, None),
+    };
+    tracing_subscriber::registry()
+        .with(stdout)
+        .with(file)
+        .with(filter)
+        .try_init()
+        .map_err(|e| Error::Logging(e.to_string()))?;
+    Ok(guard)
+}
+
+fn log_dir(config: &LogConfig) -> Option<PathBuf> {
+    config
+        .dir
+        .clone()
+        .or_else(|| dirs::data_local_dir().map(|dir| dir.join(env!("CARGO_PKG_NAME")).join("logs")))
+}
@@ -1,97 +1,274 @@
-use std::{
-    env::{self, VarError},
-    path::{Path, PathBuf},
-    sync::mpsc::channel as std_channel,
-    time::Duration,
-};
+use std::{future::pending, sync::Arc};
 
-use anyhow::Result;
-use async_recursion::async_recursion;
 use game::{watch, GameMessage};
-use lazy_static::lazy_static;
-use lockfile::RiotCredentials;
-use reqwest::Client;
-use tokio::sync::mpsc::channel;
+use serde_json::Value;
+use tokio::{
+    sync::mpsc::{channel, unbounded_channel, Receiver},
+    task::JoinHandle,
+    time::{sleep_until, Instant},
+};
+use tracing::{error, info, warn};
 
 use crate::{
-    discord::{activity::build_activity, DiscordPresence},
-    lockfile::get_lockfile_credentials,
-    valorant::websocket::receive_websocket_events,
+    cli::Args,
+    config::Config,
+    discord::{
+        activity::build_activity, idle::IdleTimer, join::handle_activity_event,
+        limiter::UpdateLimiter, DiscordEvent, DiscordPresence,
+    },
+    error::Result,
+    lockfile::find_riot_dir,
+    valorant::{
+        api::RiotApi,
+        game_state::GameState,
+        session::Session,
+        supervisor::{supervise, ClientEvent},
+    },
 };
 
+pub mod cli;
+pub mod config;
 pub mod discord;
+pub mod error;
 pub mod game;
 pub mod lockfile;
+pub mod logging;
 pub mod valorant;
 
-const DISCORD_APP_ID: i64 = 944668216486154291;
-
 #[tokio::main]
 async fn main() -> Result<()> {
+    let args = Args::parse()?;
+    let config_path = args.config.or_else(Config::default_path);
+    let created = config_path.as_ref().is_some_and(|path| !path.exists());
+    let config = match &config_path {
+        Some(path) => Config::load(path)?,
+        None => Config::default(),
+    };
+    // Logging is configured by the config, so everything before can't be logged.
+    let _log_guard = logging::init(&config.log)?;
     print_information();
-    let (tx, rx) = std_channel();
-    watch(tx, get_riot_dir().unwrap().as_path());
+    if let Some(path) = &config_path {
+        if created {
+            info!("Wrote default config to {}", path.display());
+        } else {
+            info!("Using config {}", path.display());
+        }
+    }
+    valorant::maps::init(config.maps_file.as_deref())?;
+    valorant::queues::init(config.queues_file.as_deref())?;
+    let overrides: Vec<_> = args
+        .riot_dir
+        .into_iter()
+        .chain(config.riot_dir.clone())
+        .collect();
+    let riot_dir = find_riot_dir(&overrides)?;
+    info!("Using Riot Client directory {}", riot_dir.display());
+    let (tx, mut rx) = unbounded_channel();
+    watch(tx, &riot_dir)?;
+    let (sender, mut receiver) = channel(128);
+    let mut supervisor: Option<JoinHandle<()>> = None;
+    let mut presence: Option<DiscordPresence> = None;
+    let mut riot_api: Option<Arc<dyn RiotApi>> = None;
+    let mut session: Option<Session> = None;
+    let mut last_state: Option<Box<GameState>> = None;
+    let mut idle = IdleTimer::new(config.behavior.clear_after_idle_minutes);
+    let mut limiter: UpdateLimiter<Value> = UpdateLimiter::default();
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
     loop {
-        match rx.recv() {
-            Ok(message) => match message {
-                GameMessage::GameStarted => {
-                    println!("Game Started!");
-                    let creds = get_lockfile_credentials().await?;
-                    wait_until_server_ready(&creds, Duration::from_millis(500)).await;
-                    let (sender, mut receiver) = channel(128);
-                    receive_websocket_events(sender, creds).await.unwrap();
-                    let presence = DiscordPresence::new(DISCORD_APP_ID).await;
-                    while let Some(state) = receiver.recv().await {
-                        let activity = build_activity(&state);
-                        presence.discord.update_activity(activity).await.unwrap();
+        tokio::select! {
+            _ = &mut shutdown => {
+                info!("Shutting down...");
+                if let Some(supervisor) = supervisor.take() {
+                    supervisor.abort();
+                }
+                break;
+            }
+            message = rx.recv() => match message {
+                Some(GameMessage::GameStarted) => {
+                    info!("Game Started!");
+                    // A new lockfile means new credentials, so start over without backing off.
+                    if let Some(supervisor) = supervisor.take() {
+                        supervisor.abort();
+                    }
+                    supervisor = Some(tokio::spawn(supervise(
+                        sender.clone(),
+                        riot_dir.clone(),
+                        config.debug.dump_dir.clone(),
+                    )));
+                }
+                Some(GameMessage::GameStopped) => {
+                    info!("Game Stopped!");
+                    // Otherwise a presence which arrives before the websocket closes would show
+                    // the match again.
+                    stop_supervisor(&mut supervisor, &mut receiver).await;
+                    riot_api = None;
+                    session = None;
+                    last_state = None;
+                    idle.reset();
+                    limiter.reset();
+                    if config.behavior.disconnect_on_game_stop {
+                        if let Some(presence) = presence.take() {
+                            presence.disconnect().await;
+                        }
+                    } else if let Some(presence) = &presence {
+                        presence.clear_activity().await;
                     }
-                    println!("Disconnected from websocket.");
                 }
-                GameMessage::GameStopped => {
-                    println!("Game Stopped!");
+                None => {
+                    info!("watch error: watcher stopped");
+                    break;
                 }
             },
-            Err(e) => {
-                println!("watch error: {:?}", e);
-                break;
+            Some(event) = receiver.recv() => match event {
+                ClientEvent::Connected { session: new_session, api } => {
+                    // Log files end up in bug reports, so they only contain the Riot ID if the
+                    // player shares it anyway.
+                    if config.show.riot_id {
+                        info!("Playing as {}", new_session.riot_id());
+                    } else {
+                        info!("Connected to the Riot Client session.");
+                    }
+                    session = Some(new_session);
+                    riot_api = Some(api);
+                }
+                ClientEvent::State(state) => {
+                    idle.update(&state.status, Instant::now());
+                    if presence.is_none() {
+                        presence = match DiscordPresence::new(config.app_id) {
+                            Ok(presence) => Some(presence),
+                            Err(e) => {
+                                error!("Unable to create Discord client: {}", e);
+                                None
+                            }
+                        };
+                    }
+                    if !idle.is_cleared() && last_state.as_ref() != Some(&state) {
+                        if let Some(presence) = &presence {
+                            let activity = build_activity(&state, session.as_ref(), &config);
+                            show_activity(presence, &mut limiter, activity).await;
+                        }
+                    }
+                    last_state = Some(state);
+                }
+                ClientEvent::Disconnected => {
+                    info!("Disconnected from websocket.");
+                    riot_api = None;
+                    session = None;
+                    last_state = None;
+                    idle.reset();
+                    limiter.reset();
+                    if let Some(presence) = &presence {
+                        presence.clear_activity().await;
+                    }
+                }
+            },
+            _ = wait_until(idle.deadline()) => {
+                info!("Away for too long, clearing the activity.");
+                idle.expire();
+                limiter.reset();
+                if let Some(presence) = &presence {
+                    presence.clear_activity().await;
+                }
             }
+            _ = wait_until(limiter.next_ready()) => {
+                if let (Some(presence), Some(activity)) = (&presence, limiter.take_ready()) {
+                    presence.update_activity(activity).await;
+                }
+            }
+            event = next_discord_event(&mut presence) => match event {
+                DiscordEvent::Connected => {
+                    // Whatever was shown before is gone, so the latest activity is sent again.
+                    limiter.reset();
+                    if let (Some(presence), Some(state)) = (&presence, &last_state) {
+                        if !idle.is_cleared() {
+                            let activity = build_activity(state, session.as_ref(), &config);
+                            show_activity(presence, &mut limiter, activity).await;
+                        }
+                    }
+                }
+                DiscordEvent::Disconnected => limiter.reset(),
+                DiscordEvent::Activity(event) => {
+                    if let Err(e) = handle_activity_event(event, riot_api.as_deref()).await {
+                        warn!("Unable to handle Discord activity event: {}", e);
+                    }
+                }
+            },
         }
     }
+    if let Some(presence) = presence {
+        presence.disconnect().await;
+    }
     Ok(())
 }
 
-fn print_information() {
-    println!("{} v{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
-    println!("Git Commit: {}", env!("GIT_VERSION"));
+/// Stops the supervisor and drops the events it sent before it stopped.
+async fn stop_supervisor(
+    supervisor: &mut Option<JoinHandle<()>>,
+    receiver: &mut Receiver<ClientEvent>,
+) {
+    if let Some(supervisor) = supervisor.take() {
+        supervisor.abort();
+        // Waits until the task is gone, so it can't send anything afterwards.
+        let _ = supervisor.await;
+    }
+    while receiver.try_recv().is_ok() {}
+}
+
+/// Sends an activity if Discord is connected and the rate limit allows it, otherwise it is
+/// sent once the limit allows it or replayed after Discord connects.
+async fn show_activity(
+    presence: &DiscordPresence,
+    limiter: &mut UpdateLimiter<Value>,
+    activity: Value,
+) {
+    if !presence.is_connected() {
+        return;
+    }
+    if let Some(activity) = limiter.submit(activity) {
+        presence.update_activity(activity).await;
+    }
 }
 
-#[async_recursion]
-async fn wait_until_server_ready(creds: &RiotCredentials, delay: Duration) {
-    lazy_static! {
-        static ref CLIENT: Client = reqwest::Client::builder()
-            .danger_accept_invalid_certs(true)
-            .build()
-            .unwrap();
+async fn next_discord_event(presence: &mut Option<DiscordPresence>) -> DiscordEvent {
+    match presence {
+        Some(presence) => presence.next_event().await,
+        None => pending().await,
     }
-    let result = CLIENT
-        .get(format!("https://127.0.0.1:{}/help", creds.port))
-        .basic_auth("riot", Some(&creds.password))
-        .send()
-        .await
-        .unwrap()
-        .text()
-        .await;
-    if let Ok(text) = result {
-        if text.contains("OnJsonApiEvent_chat_v4_presences") {
-            return;
+}
+
+async fn wait_until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => sleep_until(deadline).await,
+        None => pending().await,
+    }
+}
+
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+            }
+            Err(e) => {
+                warn!("Unable to listen for SIGTERM: {}", e);
+                let _ = tokio::signal::ctrl_c().await;
+            }
         }
     }
-    tokio::time::sleep(delay).await;
-    println!("Server is not ready yet. Retrying...");
-    wait_until_server_ready(creds, delay * 2).await;
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
 }
 
-fn get_riot_dir() -> Result<PathBuf, VarError> {
-    let local_app_data = env::var("LOCALAPPDATA")?;
-    Ok(Path::new(&local_app_data).join("Riot Games/Riot Client/Config/"))
+fn print_information() {
+    info!("{} v{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+    info!("Git Commit: {}", env!("GIT_VERSION"));
 }
@@ -0,0 +1,291 @@
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    api::{AgentSelection, RiotApi},
+    game_state::GameStateStatus,
+    throttle::WarningThrottle,
+};
+use crate::error::Result;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Agent {
+    pub name: String,
+    pub icon_key: String,
+    /// Whether the agent is locked in, rather than only hovered in agent select.
+    pub locked: bool,
+}
+
+/// Agents by their character id, with the key of their icon in the Discord application.
+const AGENTS: &[(&str, &str, &str)] = &[
+    ("41fb69c1-4189-7b37-f117-bcaf1e96f1bf", "Astra", "astra"),
+    ("5f8d3a7f-467b-97f3-062c-13acf203c006", "Breach", "breach"),
+    (
+        "9f0d8ba9-4140-b941-57d3-a7ad57c6b417",
+        "Brimstone",
+        "brimstone",
+    ),
+    ("22697a3d-45bf-8dd7-4fec-84a9e28c69d7", "Chamber", "chamber"),
+    ("1dbf2edd-4729-0984-3115-daa5eed44993", "Clove", "clove"),
+    ("117ed9e3-49f3-6512-3ccf-0cada7e3823b", "Cypher", "cypher"),
+    (
+        "cc8b64c8-4b25-4ff9-6e7f-37b4da43d235",
+        "Deadlock",
+        "deadlock",
+    ),
+    ("dade69b4-4f5a-8528-247b-219e5a1facd6", "Fade", "fade"),
+    ("e370fa57-4757-3604-3648-499e1f642d3f", "Gekko", "gekko"),
+    ("95b78ed7-4637-86d9-7e41-71ba8c293152", "Harbor", "harbor"),
+    ("0e38b510-41a8-5780-5e8f-568b2a4f2d6c", "Iso", "iso"),
+    ("add6443a-41bd-e414-f6ad-e58d267f4e95", "Jett", "jett"),
+    ("601dbbe7-43ce-be57-2a40-4abd24953621", "KAY/O", "kayo"),
+    ("1e58de9c-4950-5125-93e9-a0aee9f98746", "Killjoy", "killjoy"),
+    ("bb2a4828-46eb-8cd1-e765-15848195d751", "Neon", "neon"),
+    ("8e253930-4c05-31dd-1b6c-968525494517", "Omen", "omen"),
+    ("eb93336a-449b-9c1b-0a54-a891f7921d69", "Phoenix", "phoenix"),
+    ("f94c3b30-42be-e959-889c-5aa313dba261", "Raze", "raze"),
+    ("a3bfb853-43b2-7238-a4f1-ad90e9e46bcc", "Reyna", "reyna"),
+    ("569fdd95-4d10-43ab-ca70-79becc718b46", "Sage", "sage"),
+    ("6f2a04ca-43e0-be17-7f36-b3908627744d", "Skye", "skye"),
+    ("320b2a48-4d9b-a075-30f1-1f93a9b638fa", "Sova", "sova"),
+    ("707eab51-4836-f488-046a-cda6bf494859", "Viper", "viper"),
+    ("efba5359-4016-a1e5-7626-b1ae76895940", "Vyse", "vyse"),
+    ("7f94d92c-4234-0a36-9646-3a87eb8b5c89", "Yoru", "yoru"),
+];
+
+/// Looks up an agent by its character id, agents we don't know yet are left out.
+pub fn determine_agent(character_id: &str, locked: bool) -> Option<Agent> {
+    AGENTS
+        .iter()
+        .find(|(id, _, _)| id.eq_ignore_ascii_case(character_id))
+        .map(|(_, name, icon_key)| Agent {
+            name: (*name).to_owned(),
+            icon_key: (*icon_key).to_owned(),
+            locked,
+        })
+}
+
+/// Lookups which fail are only warned about once in this interval, as they are retried.
+const WARNING_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Agent select is asked for again in this interval, as locking in doesn't change the presence.
+const PREGAME_POLL_INTERVAL: Duration = Duration::from_secs(3);
+
+/// Lookups which miss are retried after this delay, which doubles with every miss in a row.
+const RETRY_INITIAL_DELAY: Duration = Duration::from_secs(5);
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    PreGame,
+    InGame,
+}
+
+/// Asks the game servers which agent the player picked, as the presence doesn't contain it.
+pub struct AgentTracker {
+    /// The phase the agent is looked up for, `None` outside of agent select and matches.
+    phase: Option<Phase>,
+    /// The agent the last successful lookup of this phase found.
+    agent: Option<Agent>,
+    /// When the agent is due to be looked up again, `None` once the agent of the running
+    /// match is known, as it can't change until the match is over.
+    next_lookup: Option<Instant>,
+    retry_delay: Duration,
+    warnings: WarningThrottle<()>,
+}
+
+impl Default for AgentTracker {
+    fn default() -> AgentTracker {
+        AgentTracker {
+            phase: None,
+            agent: None,
+            next_lookup: None,
+            retry_delay: RETRY_INITIAL_DELAY,
+            warnings: WarningThrottle::new(WARNING_INTERVAL),
+        }
+    }
+}
+
+impl AgentTracker {
+    /// The agent the player picked in `status`, only asking the game servers when a lookup
+    /// is due.
+    pub async fn resolve(&mut self, api: &dyn RiotApi, status: &GameStateStatus) -> Option<Agent> {
+        self.resolve_at(api, status, Instant::now()).await
+    }
+
+    /// When the agent is due to be looked up again, `None` if it is known for good.
+    pub fn next_lookup(&self) -> Option<Instant> {
+        self.next_lookup
+    }
+
+    async fn resolve_at(
+        &mut self,
+        api: &dyn RiotApi,
+        status: &GameStateStatus,
+        now: Instant,
+    ) -> Option<Agent> {
+        let phase = match status {
+            GameStateStatus::PreGame => Some(Phase::PreGame),
+            GameStateStatus::InGame => Some(Phase::InGame),
+            _ => None,
+        };
+        if phase != self.phase {
+            self.phase = phase;
+            self.agent = None;
+            self.next_lookup = phase.map(|_| now);
+            self.retry_delay = RETRY_INITIAL_DELAY;
+        }
+        let phase = self.phase?;
+        match self.next_lookup {
+            Some(at) if at <= now => {}
+            _ => return self.agent.clone(),
+        }
+        let result = match phase {
+            Phase::PreGame => api.pregame_agent().await,
+            Phase::InGame => api.coregame_agent().await,
+        };
+        self.update(phase, result, now);
+        self.agent.clone()
+    }
+
+    fn update(&mut self, phase: Phase, result: Result<Option<AgentSelection>>, now: Instant) {
+        match (phase, result) {
+            (_, Ok(Some(selection))) => {
+                self.agent = determine_agent(&selection.character_id, selection.locked);
+                self.retry_delay = RETRY_INITIAL_DELAY;
+                self.next_lookup = match phase {
+                    Phase::PreGame => Some(now + PREGAME_POLL_INTERVAL),
+                    Phase::InGame => None,
+                };
+            }
+            // Agent select has no selection until the player hovers an agent.
+            (Phase::PreGame, Ok(None)) => {
+                self.agent = None;
+                self.retry_delay = RETRY_INITIAL_DELAY;
+                self.next_lookup = Some(now + PREGAME_POLL_INTERVAL);
+            }
+            // The game servers may not know about a match which just started yet.
+            (_, result) => {
+                if let Err(e) = result {
+                    let message = format!("Unable to determine the selected agent: {}", e);
+                    self.warnings.warn((), now, message);
+                }
+                self.next_lookup = Some(now + self.retry_delay);
+                self.retry_delay = (self.retry_delay * 2).min(RETRY_MAX_DELAY);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::valorant::testing::{menu, StandInApi};
+
+    const JETT: &str = "add6443a-41bd-e414-f6ad-e58d267f4e95";
+
+    #[test]
+    fn determines_agents_by_character_id() {
+        let agent = determine_agent(&JETT.to_uppercase(), true).unwrap();
+        assert_eq!(agent.name, "Jett");
+        assert_eq!(agent.icon_key, "jett");
+        assert!(agent.locked);
+        assert_eq!(determine_agent("unknown", true), None);
+    }
+
+    /// Resolves the agent at each of the offsets from the start, returning the number of
+    /// lookups after each of them.
+    async fn lookups_at(
+        api: &StandInApi,
+        status: GameStateStatus,
+        offsets: &[u64],
+    ) -> (AgentTracker, Vec<u32>) {
+        let mut tracker = AgentTracker::default();
+        let start = Instant::now();
+        let mut lookups = Vec::new();
+        for offset in offsets {
+            let now = start + Duration::from_secs(*offset);
+            tracker.resolve_at(api, &status, now).await;
+            lookups.push(api.pregame_calls() + api.coregame_calls());
+        }
+        (tracker, lookups)
+    }
+
+    #[tokio::test]
+    async fn polls_agent_select() {
+        let mut api = StandInApi::default();
+        let mut tracker = AgentTracker::default();
+        let start = Instant::now();
+        let pregame = GameStateStatus::PreGame;
+        assert_eq!(tracker.resolve_at(&api, &pregame, start).await, None);
+        assert_eq!(tracker.next_lookup(), Some(start + PREGAME_POLL_INTERVAL));
+        // Hovering an agent doesn't change the presence, the next poll finds it.
+        api.character_id = Some(JETT);
+        let soon = start + PREGAME_POLL_INTERVAL / 2;
+        assert_eq!(tracker.resolve_at(&api, &pregame, soon).await, None);
+        let later = start + PREGAME_POLL_INTERVAL;
+        let agent = tracker.resolve_at(&api, &pregame, later).await;
+        assert_eq!(agent.map(|agent| agent.locked), Some(false));
+        assert_eq!(api.pregame_calls(), 2);
+        assert_eq!(tracker.next_lookup(), Some(later + PREGAME_POLL_INTERVAL));
+    }
+
+    #[tokio::test]
+    async fn backs_off_after_failures_in_agent_select() {
+        let api = StandInApi {
+            fail: true,
+            ..StandInApi::default()
+        };
+        let (_, lookups) = lookups_at(&api, GameStateStatus::PreGame, &[0, 3, 5, 10, 15]).await;
+        assert_eq!(lookups, [1, 1, 2, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn keeps_the_agent_of_the_running_match() {
+        let api = StandInApi {
+            character_id: Some(JETT),
+            ..StandInApi::default()
+        };
+        let mut tracker = AgentTracker::default();
+        for _ in 0..3 {
+            let agent = tracker.resolve(&api, &GameStateStatus::InGame).await;
+            assert_eq!(agent.map(|agent| agent.name), Some("Jett".to_owned()));
+        }
+        assert_eq!(api.coregame_calls(), 1);
+        assert_eq!(tracker.next_lookup(), None);
+        // The next match is asked for again.
+        assert_eq!(tracker.resolve(&api, &menu(false, false)).await, None);
+        tracker.resolve(&api, &GameStateStatus::InGame).await;
+        assert_eq!(api.coregame_calls(), 2);
+    }
+
+    #[tokio::test]
+    async fn retries_misses_of_the_running_match_with_a_bounded_backoff() {
+        for fail in [false, true] {
+            let api = StandInApi {
+                fail,
+                ..StandInApi::default()
+            };
+            // Retried after 5, 10, 20, 40 and then every 60 seconds.
+            let offsets = [0, 4, 5, 14, 15, 35, 75, 135, 194, 195];
+            let (tracker, lookups) = lookups_at(&api, GameStateStatus::InGame, &offsets).await;
+            assert_eq!(lookups, [1, 1, 2, 2, 3, 4, 5, 6, 6, 7]);
+            assert!(tracker.next_lookup().is_some());
+        }
+    }
+
+    #[tokio::test]
+    async fn stops_retrying_once_the_match_is_known() {
+        let mut api = StandInApi::default();
+        let mut tracker = AgentTracker::default();
+        let start = Instant::now();
+        let in_game = GameStateStatus::InGame;
+        assert_eq!(tracker.resolve_at(&api, &in_game, start).await, None);
+        api.character_id = Some(JETT);
+        let later = start + RETRY_INITIAL_DELAY;
+        assert!(tracker.resolve_at(&api, &in_game, later).await.is_some());
+        assert_eq!(tracker.next_lookup(), None);
+        assert_eq!(api.coregame_calls(), 2);
+    }
+}
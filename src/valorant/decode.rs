@@ -0,0 +1,301 @@
+use std::{
+    error::Error,
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+    string::FromUtf8Error,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use base64::decode;
+use tracing::warn;
+
+use super::{
+    presence::{ParsedPresence, Presence},
+    throttle::WarningThrottle,
+    wamp::EventDecodeError,
+};
+
+/// Each stage only warns once in this interval, so a schema change doesn't flood the output.
+const WARNING_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+pub enum PresenceDecodeError {
+    /// The presence event doesn't match [`PresencesEvent`](super::presence::PresencesEvent).
+    Event(EventDecodeError),
+    /// The `private` field isn't valid base64.
+    Base64(base64::DecodeError),
+    /// The decoded `private` field isn't valid UTF-8.
+    Utf8(FromUtf8Error),
+    /// The decoded `private` field doesn't match [`ParsedPresence`].
+    Json {
+        path: String,
+        source: serde_json::Error,
+    },
+}
+
+impl PresenceDecodeError {
+    pub fn stage(&self) -> &'static str {
+        match self {
+            PresenceDecodeError::Event(_) => "event",
+            PresenceDecodeError::Base64(_) => "base64",
+            PresenceDecodeError::Utf8(_) => "utf8",
+            PresenceDecodeError::Json { .. } => "json",
+        }
+    }
+}
+
+impl Display for PresenceDecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PresenceDecodeError::Event(e) => e.fmt(f),
+            PresenceDecodeError::Base64(e) => {
+                f.write_fmt(format_args!("private presence is not base64: {}", e))
+            }
+            PresenceDecodeError::Utf8(e) => {
+                f.write_fmt(format_args!("private presence is not UTF-8: {}", e))
+            }
+            PresenceDecodeError::Json { path, source } => f.write_fmt(format_args!(
+                "unable to parse private presence at {}: {}",
+                path, source
+            )),
+        }
+    }
+}
+
+impl Error for PresenceDecodeError {}
+
+/// Decodes the base64 encoded `private` field of a presence.
+pub fn decode_private(presence: &Presence) -> Result<ParsedPresence, PresenceDecodeError> {
+    let bytes = decode(&presence.private).map_err(PresenceDecodeError::Base64)?;
+    let json = String::from_utf8(bytes).map_err(PresenceDecodeError::Utf8)?;
+    let deserializer = &mut serde_json::Deserializer::from_str(&json);
+    serde_path_to_error::deserialize(deserializer).map_err(|e| PresenceDecodeError::Json {
+        path: e.path().to_string(),
+        source: e.into_inner(),
+    })
+}
+
+/// Reports decode failures as rate-limited warnings and optionally dumps the payloads.
+pub struct DecodeReporter {
+    dump_dir: Option<PathBuf>,
+    warnings: WarningThrottle<&'static str>,
+}
+
+impl DecodeReporter {
+    pub fn new(dump_dir: Option<PathBuf>) -> DecodeReporter {
+        DecodeReporter {
+            dump_dir,
+            warnings: WarningThrottle::new(WARNING_INTERVAL),
+        }
+    }
+
+    pub fn report(&mut self, error: &PresenceDecodeError, payload: &str) {
+        self.report_at(error, payload, Instant::now());
+    }
+
+    /// Reports an error which happened at `now`, returning how many similar errors were
+    /// suppressed before it, or `None` if it is suppressed itself.
+    fn report_at(
+        &mut self,
+        error: &PresenceDecodeError,
+        payload: &str,
+        now: Instant,
+    ) -> Option<u32> {
+        let stage = error.stage();
+        let message = format!("Failed to decode presence: {}", error);
+        let suppressed = self.warnings.warn(stage, now, message)?;
+        if let Some(dir) = &self.dump_dir {
+            dump(dir, stage, payload);
+        }
+        Some(suppressed)
+    }
+}
+
+fn dump(dir: &Path, stage: &str, payload: &str) {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let path = dir.join(format!("presence-{}-{}.json", timestamp, stage));
+    let result = fs::create_dir_all(dir).and_then(|_| fs::write(&path, payload));
+    match result {
+        Ok(()) => warn!("Dumped the payload to {}", path.display()),
+        Err(e) => warn!("Unable to dump the payload to {}: {}", path.display(), e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::encode;
+    use serde_json::json;
+
+    use super::*;
+
+    fn presence(private: impl AsRef<[u8]>) -> Presence {
+        Presence {
+            puuid: "puuid".to_owned(),
+            product: "valorant".to_owned(),
+            private: encode(private),
+            game_name: "Name".to_owned(),
+            game_tag: "TAG".to_owned(),
+        }
+    }
+
+    fn base64_error() -> PresenceDecodeError {
+        decode_private(&Presence {
+            private: "not base64!".to_owned(),
+            ..presence("")
+        })
+        .unwrap_err()
+    }
+
+    fn utf8_error() -> PresenceDecodeError {
+        decode_private(&presence([0xff, 0xfe])).unwrap_err()
+    }
+
+    fn decode_fixture(fixture: &str) -> ParsedPresence {
+        decode_private(&presence(fixture)).unwrap()
+    }
+
+    #[test]
+    fn decodes_full_presence() {
+        let presence = decode_fixture(include_str!("fixtures/presence_full.json"));
+        assert!(presence.is_valid);
+        assert!(!presence.is_idle);
+        assert_eq!(presence.session_loop_state, "INGAME");
+        assert_eq!(presence.provisioning_flow, "Matchmaking");
+        assert_eq!(presence.match_map, "/Game/Maps/Pitt/Pitt");
+        assert_eq!(
+            presence.party_owner_match_current_team.as_deref(),
+            Some("Blue")
+        );
+        assert_eq!(presence.party_owner_match_score_ally_team, 7);
+        assert_eq!(presence.party_owner_match_score_enemy_team, 5);
+        assert_eq!(presence.custom_game_name.as_deref(), Some(""));
+        assert_eq!(
+            presence.party_id.as_deref(),
+            Some("5a3b6c2e-1f4d-4e8a-9b7c-0d2e3f4a5b6c")
+        );
+        assert!(presence.is_party_owner);
+        assert_eq!(presence.party_accessibility.as_deref(), Some("CLOSED"));
+        assert!(!presence.party_lfm);
+        assert_eq!(presence.party_size, 2);
+        assert_eq!(presence.max_party_size, 5);
+        assert_eq!(presence.party_version, 1728136549432);
+        assert_eq!(presence.queue_id, "competitive");
+        assert_eq!(
+            presence.queue_entry_time.as_deref(),
+            Some("2024.10.05-13.52.11")
+        );
+        assert_eq!(presence.account_level, 142);
+        assert_eq!(presence.competitive_tier, 19);
+        // Every field of the payload is modeled.
+        assert_eq!(presence.extra, json!({}));
+    }
+
+    #[test]
+    fn decodes_minimal_presence_with_defaults() {
+        let presence = decode_fixture(include_str!("fixtures/presence_minimal.json"));
+        assert!(presence.is_valid);
+        assert_eq!(presence.session_loop_state, "MENUS");
+        assert_eq!(presence.queue_id, "unrated");
+        assert!(!presence.is_idle);
+        assert_eq!(presence.party_owner_session_loop_state, "");
+        assert_eq!(presence.match_map, "");
+        assert_eq!(presence.party_owner_match_current_team, None);
+        assert_eq!(presence.party_owner_match_score_ally_team, 0);
+        assert_eq!(presence.custom_game_name, None);
+        assert_eq!(presence.custom_game_team, None);
+        assert_eq!(presence.party_id, None);
+        assert_eq!(presence.party_accessibility, None);
+        assert_eq!(presence.party_size, 0);
+        assert_eq!(presence.max_party_size, 0);
+        assert_eq!(presence.queue_entry_time, None);
+        assert_eq!(presence.competitive_tier, 0);
+        assert_eq!(presence.extra, json!({}));
+    }
+
+    #[test]
+    fn keeps_unknown_fields() {
+        let presence = decode_fixture(include_str!("fixtures/presence_unknown_fields.json"));
+        assert!(presence.is_idle);
+        assert_eq!(presence.party_state, "MATCHMAKING");
+        assert_eq!(presence.party_accessibility.as_deref(), Some("OPEN"));
+        assert_eq!(presence.party_owner_match_current_team, None);
+        assert_eq!(
+            presence.extra,
+            json!({
+                "premierPresenceData": {
+                    "rosterName": "",
+                    "rosterTag": "",
+                    "division": 0
+                },
+                "newFieldRiotAddedLater": 3
+            })
+        );
+    }
+
+    #[test]
+    fn reports_the_failed_stage() {
+        let error = decode_private(&Presence {
+            private: "not base64!".to_owned(),
+            ..presence("")
+        })
+        .unwrap_err();
+        assert_eq!(error.stage(), "base64");
+        let error = decode_private(&presence([0xff, 0xfe])).unwrap_err();
+        assert_eq!(error.stage(), "utf8");
+        let error = decode_private(&presence("not json")).unwrap_err();
+        assert_eq!(error.stage(), "json");
+    }
+
+    #[test]
+    fn reports_the_path_of_mismatched_fields() {
+        let error = decode_private(&presence(r#"{"partySize": "two"}"#)).unwrap_err();
+        match error {
+            PresenceDecodeError::Json { path, .. } => assert_eq!(path, "partySize"),
+            error => panic!("unexpected error {}", error),
+        }
+    }
+
+    #[test]
+    fn suppresses_warnings_per_stage_within_the_interval() {
+        let mut reporter = DecodeReporter::new(None);
+        let start = Instant::now();
+        assert_eq!(reporter.report_at(&base64_error(), "", start), Some(0));
+        assert_eq!(reporter.report_at(&base64_error(), "", start), None);
+        // Other stages have their own interval.
+        assert_eq!(reporter.report_at(&utf8_error(), "", start), Some(0));
+        let later = start + WARNING_INTERVAL - Duration::from_secs(1);
+        assert_eq!(reporter.report_at(&base64_error(), "", later), None);
+        let later = start + WARNING_INTERVAL;
+        assert_eq!(reporter.report_at(&base64_error(), "", later), Some(2));
+        assert_eq!(reporter.report_at(&base64_error(), "", later), None);
+        let later = later + WARNING_INTERVAL;
+        assert_eq!(reporter.report_at(&base64_error(), "", later), Some(1));
+    }
+
+    #[test]
+    fn dumps_the_payload_of_warned_errors() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let dump_dir = dir.path().join("dumps");
+        let mut reporter = DecodeReporter::new(Some(dump_dir.clone()));
+        let start = Instant::now();
+        reporter.report_at(&base64_error(), "first", start);
+        // Suppressed errors aren't dumped either.
+        reporter.report_at(&base64_error(), "second", start);
+        let files: Vec<_> = fs::read_dir(&dump_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(files.len(), 1);
+        let name = files[0].file_name().unwrap().to_str().unwrap();
+        let timestamp = name
+            .strip_prefix("presence-")
+            .and_then(|name| name.strip_suffix("-base64.json"))
+            .unwrap();
+        assert!(timestamp.parse::<u128>().is_ok(), "{}", name);
+        assert_eq!(fs::read_to_string(&files[0]).unwrap(), "first");
+    }
+}
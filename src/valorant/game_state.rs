@@ -1,56 +1,113 @@
 use serde::{Deserialize, Serialize};
 
-use super::presence_analyzer::Map;
+use super::{
+    agents::Agent,
+    maps::Map,
+    queues::{lookup_queue, QueueCategory, QueueInfo},
+    ranks::Rank,
+};
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct GameState {
     pub game_mode: GameMode,
     pub status: GameStateStatus,
     pub scores: Scores,
     pub party: Party,
     pub map: Map,
+    pub rank: Option<Rank>,
+    /// Filled in from the game servers, the presence doesn't contain it.
+    pub agent: Option<Agent>,
+    /// Lobby details, only present in custom games.
+    pub custom: Option<CustomGame>,
+    /// Unix timestamp of when the current phase (queue, agent select or match) started.
+    pub started_at: Option<i64>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub enum GameStateStatus {
     InGame,
     PreGame,
-    Menu { in_queue: bool },
+    Menu {
+        in_queue: bool,
+        /// The player has been away from the keyboard for a while.
+        idle: bool,
+    },
+    /// A session loop state we don't know about yet.
+    Unknown(String),
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct Scores {
     pub ally_team: u32,
     pub enemy_team: u32,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct Party {
+    /// The Riot party id, which must not be shown to anyone as is.
+    pub id: Option<String>,
     pub size: u32,
     pub max_size: u32,
+    pub open: bool,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub enum GameMode {
-    Unrated,
-    Competitive,
-    SpikeRush,
-    Deathmatch,
-    Replication,
-    CustomGame,
-    Unknown,
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CustomGame {
+    pub name: Option<String>,
+    pub team: Option<CustomTeam>,
 }
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CustomTeam {
+    /// The teams start on opposite sides and swap at halftime, so they aren't named after a side.
+    TeamA,
+    TeamB,
+    Spectators,
+    Coaches,
+}
+
+impl CustomTeam {
+    /// Parses the team names of the presence, like `TeamOne` or `TeamSpectate`.
+    pub fn parse(team: &str) -> Option<CustomTeam> {
+        match team {
+            "TeamOne" => Some(CustomTeam::TeamA),
+            "TeamTwo" => Some(CustomTeam::TeamB),
+            "TeamSpectate" => Some(CustomTeam::Spectators),
+            "TeamOneCoaches" | "TeamTwoCoaches" => Some(CustomTeam::Coaches),
+            _ => None,
+        }
+    }
+
+    /// What the player does in the team, e.g. "Spectating", or the name of the team they play in.
+    pub fn role(&self) -> &'static str {
+        match self {
+            CustomTeam::TeamA => "Team A",
+            CustomTeam::TeamB => "Team B",
+            CustomTeam::Spectators => "Spectating",
+            CustomTeam::Coaches => "Coaching",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct GameMode {
+    pub queue_id: String,
+    pub info: QueueInfo,
+}
+
 impl GameMode {
-    pub fn get_display_name(&self) -> String {
-        match *self {
-            GameMode::Unrated => "Unrated",
-            GameMode::Competitive => "Competitive",
-            GameMode::SpikeRush => "Spike Rush",
-            GameMode::Deathmatch => "Deathmatch",
-            GameMode::Replication => "Replication",
-            GameMode::CustomGame => "Custom Game",
-            GameMode::Unknown => "Unknown",
+    pub fn new(queue_id: &str) -> GameMode {
+        GameMode {
+            queue_id: queue_id.to_owned(),
+            info: lookup_queue(queue_id),
         }
-        .to_owned()
+    }
+
+    pub fn get_display_name(&self) -> String {
+        self.info.name.clone()
+    }
+
+    pub fn is_custom(&self) -> bool {
+        self.info.category == QueueCategory::Custom
     }
 }
@@ -0,0 +1,135 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use super::table::{DataTable, TableError, TableFile};
+
+const DEFAULT_MAPS: &str = include_str!("data/maps.toml");
+
+static MAPS: DataTable<MapFile> = DataTable::new("map table", DEFAULT_MAPS);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Map {
+    pub display_name: String,
+    pub image_key: Option<String>,
+    pub map_type: MapType,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MapType {
+    Standard,
+    Range,
+    TeamDeathmatch,
+    Skirmish,
+    Unknown,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MapFile {
+    #[serde(default)]
+    map: Vec<MapEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MapEntry {
+    path: String,
+    name: String,
+    image: Option<String>,
+    #[serde(rename = "type", default = "default_map_type")]
+    map_type: MapType,
+}
+
+fn default_map_type() -> MapType {
+    MapType::Standard
+}
+
+impl TableFile for MapFile {
+    type Entry = Map;
+
+    fn into_entries(self) -> Vec<(String, Map)> {
+        self.map
+            .into_iter()
+            .map(|entry| {
+                let map = Map {
+                    display_name: entry.name,
+                    image_key: entry.image,
+                    map_type: entry.map_type,
+                };
+                (entry.path, map)
+            })
+            .collect()
+    }
+}
+
+/// Loads the map table, letting entries from `overrides` replace or extend the bundled maps.
+///
+/// Has to be called before the first map is looked up, later calls are ignored.
+pub fn init(overrides: Option<&Path>) -> Result<(), TableError> {
+    MAPS.init(overrides)
+}
+
+pub fn determine_map(match_map: &str) -> Map {
+    match MAPS.get(match_map) {
+        Some(map) => map.clone(),
+        None => Map {
+            // Show the asset name rather than the whole path of maps we don't know yet.
+            display_name: match_map.rsplit('/').next().unwrap_or_default().to_owned(),
+            image_key: None,
+            map_type: MapType::Unknown,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::valorant::table::parse;
+
+    #[test]
+    fn bundled_maps_parse() {
+        let maps = parse::<MapFile>(DEFAULT_MAPS).unwrap();
+        assert_eq!(
+            maps["/Game/Maps/Pitt/Pitt"],
+            Map {
+                display_name: "Pearl".to_owned(),
+                image_key: Some("pearl".to_owned()),
+                map_type: MapType::Standard,
+            }
+        );
+        for range in ["/Game/Maps/Poveglia/Range", "/Game/Maps/PovegliaV2/RangeV2"] {
+            assert_eq!(maps[range].display_name, "The Range");
+            assert_eq!(maps[range].map_type, MapType::Range);
+        }
+        assert_eq!(
+            maps["/Game/Maps/HURM/HURM_Alley/HURM_Alley"].map_type,
+            MapType::TeamDeathmatch
+        );
+    }
+
+    #[test]
+    fn map_type_defaults_to_standard() {
+        let maps =
+            parse::<MapFile>("[[map]]\npath = \"/Game/Maps/New/New\"\nname = \"New\"\n").unwrap();
+        assert_eq!(maps["/Game/Maps/New/New"].map_type, MapType::Standard);
+        assert_eq!(maps["/Game/Maps/New/New"].image_key, None);
+    }
+
+    #[test]
+    fn rejects_unknown_fields() {
+        assert!(
+            parse::<MapFile>("[[map]]\npath = \"a\"\nname = \"A\"\ncolor = \"red\"\n").is_err()
+        );
+    }
+
+    #[test]
+    fn unknown_maps_show_their_asset_name() {
+        let map = determine_map("/Game/Maps/Unreleased/Unreleased_Map");
+        assert_eq!(map.display_name, "Unreleased_Map");
+        assert_eq!(map.image_key, None);
+        assert_eq!(map.map_type, MapType::Unknown);
+        assert_eq!(determine_map("/Game/Maps/Pitt/Pitt").display_name, "Pearl");
+    }
+}
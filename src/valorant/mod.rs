@@ -1,5 +1,17 @@
-pub mod session;
-pub mod presence;
-pub mod websocket;
+pub mod agents;
+pub mod api;
+pub mod decode;
 pub mod game_state;
+pub mod maps;
+pub mod presence;
 pub mod presence_analyzer;
+pub mod queues;
+pub mod ranks;
+pub mod session;
+pub mod supervisor;
+pub mod table;
+#[cfg(test)]
+pub mod testing;
+pub mod throttle;
+pub mod wamp;
+pub mod websocket;
@@ -1,22 +1,8 @@
 use serde::Deserialize;
-use serde_tuple::Deserialize_tuple;
-
-#[derive(Deserialize_tuple, Debug)]
-pub struct PresenceResponse {
-    pub number: u8,
-    pub message: String,
-    pub data: PresenceData,
-}
-
-#[derive(Deserialize, Debug)]
-pub struct PresenceData {
-    pub data: PresenceDataData,
-    #[serde(rename = "eventType")]
-    pub event_type: String,
-}
 
+/// The data of the events of `/chat/v4/presences`.
 #[derive(Deserialize, Debug)]
-pub struct PresenceDataData {
+pub struct PresencesEvent {
     pub presences: Vec<Presence>,
 }
 
@@ -25,26 +11,52 @@ pub struct Presence {
     pub puuid: String,
     pub product: String,
     pub private: String,
+    #[serde(default)]
+    pub game_name: String,
+    #[serde(default)]
+    pub game_tag: String,
 }
 
-#[derive(Deserialize, Debug)]
+/// The decoded `private` part of a Valorant presence.
+///
+/// Riot adds and removes fields regularly, so everything has a default and fields we don't
+/// model yet end up in `extra`.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, rename_all = "camelCase")]
 pub struct ParsedPresence {
-    #[serde(rename = "sessionLoopState")]
+    pub is_valid: bool,
+    pub is_idle: bool,
     pub session_loop_state: String,
-    #[serde(rename = "partyOwnerMatchScoreAllyTeam")]
+    pub party_owner_session_loop_state: String,
+    pub provisioning_flow: String,
+    pub party_owner_provisioning_flow: String,
+    pub match_map: String,
+    pub party_owner_match_map: String,
+    pub party_owner_match_current_team: Option<String>,
     pub party_owner_match_score_ally_team: u32,
-    #[serde(rename = "partyOwnerMatchScoreEnemyTeam")]
     pub party_owner_match_score_enemy_team: u32,
-    #[serde(rename = "provisioningFlow")]
-    pub provisioning_flo: String,
-    #[serde(rename = "matchMap")]
-    pub match_map: String,
-    #[serde(rename = "partyState")]
+    pub custom_game_name: Option<String>,
+    pub custom_game_team: Option<String>,
+    pub party_id: Option<String>,
+    pub is_party_owner: bool,
     pub party_state: String,
-    #[serde(rename = "maxPartySize")]
+    pub party_accessibility: Option<String>,
+    #[serde(rename = "partyLFM")]
+    pub party_lfm: bool,
+    pub party_client_version: Option<String>,
+    pub party_size: u32,
     pub max_party_size: u32,
-    #[serde(rename = "queueId")]
+    pub party_version: u64,
     pub queue_id: String,
-    #[serde(rename = "partySize")]
-    pub party_size: u32,
+    pub queue_entry_time: Option<String>,
+    pub tournament_id: Option<String>,
+    pub roster_id: Option<String>,
+    pub player_card_id: Option<String>,
+    pub player_title_id: Option<String>,
+    pub preferred_level_border_id: Option<String>,
+    pub account_level: u32,
+    pub competitive_tier: u32,
+    pub leaderboard_position: u32,
+    #[serde(flatten)]
+    pub extra: serde_json::Value,
 }
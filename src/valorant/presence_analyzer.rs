@@ -1,15 +1,23 @@
-use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tracing::warn;
 
 use super::{
-    game_state::{GameMode, GameState, GameStateStatus, Party, Scores},
+    game_state::{CustomGame, CustomTeam, GameMode, GameState, GameStateStatus, Party, Scores},
+    maps::determine_map,
     presence::ParsedPresence,
+    queues::CUSTOM_QUEUE,
+    ranks::determine_rank,
 };
+
 pub fn analyze_presence(presence: &ParsedPresence) -> GameState {
     let game_mode = determine_game_mode(presence);
 
     let party = Party {
+        id: presence.party_id.clone().filter(|id| !id.is_empty()),
         size: presence.party_size,
         max_size: presence.max_party_size,
+        open: presence.party_accessibility.as_deref() == Some("OPEN"),
     };
 
     let scores = Scores {
@@ -19,7 +27,25 @@ pub fn analyze_presence(presence: &ParsedPresence) -> GameState {
 
     let status = determine_status(presence);
 
-    let map = determine_map(&presence.match_map);
+    let custom = game_mode.is_custom().then(|| CustomGame {
+        name: presence
+            .custom_game_name
+            .clone()
+            .filter(|name| !name.is_empty()),
+        team: presence
+            .custom_game_team
+            .as_deref()
+            .and_then(CustomTeam::parse),
+    });
+
+    // Only the owner of a custom game lobby has the chosen map as their match map.
+    let map = if custom.is_some() && presence.match_map.is_empty() {
+        determine_map(&presence.party_owner_match_map)
+    } else {
+        determine_map(&presence.match_map)
+    };
+
+    let rank = determine_rank(presence.competitive_tier);
 
     GameState {
         game_mode,
@@ -27,74 +53,316 @@ pub fn analyze_presence(presence: &ParsedPresence) -> GameState {
         scores,
         status,
         map,
+        rank,
+        agent: None,
+        custom,
+        started_at: None,
     }
 }
 
-macro_rules! map_key {
-    ($single:expr) => {
-        map_key!($single, $single)
-    };
-    ($first:expr, $second:expr) => {
-        concat!("/Game/Maps/", $first, "/", $second)
-    };
+/// Remembers when the current phase of the game started, so the activity can show a timer.
+#[derive(Default)]
+pub struct PresenceTracker {
+    phase: Option<Phase>,
+    started_at: Option<i64>,
+    rounds_played: u32,
+    match_map: String,
+    /// The unknown session loop state which was warned about last, so a long replay or a new
+    /// state of the game is only warned about once.
+    warned_state: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    Menu,
+    Idle,
+    Queue,
+    PreGame,
+    InGame,
+    Unknown,
 }
 
-macro_rules! map {
-    ($name:expr, $key:expr) => {
-        Map {
-            display_name: $name.to_owned(),
-            image_key: Some($key.to_owned()),
+impl Phase {
+    fn of(status: &GameStateStatus) -> Phase {
+        match status {
+            GameStateStatus::Menu { in_queue: true, .. } => Phase::Queue,
+            GameStateStatus::Menu { idle: true, .. } => Phase::Idle,
+            GameStateStatus::Menu { .. } => Phase::Menu,
+            GameStateStatus::PreGame => Phase::PreGame,
+            GameStateStatus::InGame => Phase::InGame,
+            GameStateStatus::Unknown(_) => Phase::Unknown,
         }
-    };
-    ($name:expr) => {
-        Map {
-            display_name: $name.to_owned(),
-            image_key: None,
+    }
+}
+
+impl PresenceTracker {
+    pub fn analyze(&mut self, presence: &ParsedPresence) -> GameState {
+        let mut state = analyze_presence(presence);
+        if let GameStateStatus::Unknown(loop_state) = &state.status {
+            self.warn_unknown_state(loop_state);
         }
-    };
+        let phase = Phase::of(&state.status);
+        let rounds_played = state.scores.ally_team + state.scores.enemy_team;
+        // Custom games can be restarted without leaving the match, which resets the score.
+        let new_match = phase == Phase::InGame
+            && self.phase == Some(Phase::InGame)
+            && (rounds_played < self.rounds_played || presence.match_map != self.match_map);
+        if self.phase != Some(phase) || new_match {
+            self.started_at = match phase {
+                Phase::Menu | Phase::Unknown => None,
+                Phase::Queue => presence
+                    .queue_entry_time
+                    .as_deref()
+                    .and_then(parse_queue_entry_time)
+                    .or_else(|| Some(unix_now())),
+                Phase::Idle | Phase::PreGame | Phase::InGame => Some(unix_now()),
+            };
+        }
+        self.phase = Some(phase);
+        self.rounds_played = rounds_played;
+        self.match_map = presence.match_map.clone();
+        state.started_at = self.started_at;
+        state
+    }
+
+    /// Warns about an unknown session loop state, returning whether it was warned about.
+    fn warn_unknown_state(&mut self, loop_state: &str) -> bool {
+        if self.warned_state.as_deref() == Some(loop_state) {
+            return false;
+        }
+        warn!("Unknown session loop state {}.", loop_state);
+        self.warned_state = Some(loop_state.to_owned());
+        true
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Map {
-    pub display_name: String,
-    pub image_key: Option<String>,
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs() as i64)
 }
 
-fn determine_map(match_map: &str) -> Map {
-    match match_map {
-        map_key!("Ascent") => map!("Ascent", "ascent"),
-        map_key!("Bonsai") => map!("Split", "split"),
-        map_key!("Canyon") => map!("Fracture", "fracture"),
-        map_key!("Duality") => map!("Bind", "bind"),
-        map_key!("Foxtrot") => map!("Breeze", "breeze"),
-        map_key!("Port") => map!("Icebox", "icebox"),
-        map_key!("Poveglia", "Range") => map!("The Range", "range"),
-        map_key!("Triad") => map!("Haven", "haven"),
-        _ => map!(match_map),
+/// Parses the UTC `queueEntryTime` of a presence, which looks like `2022.05.21-14.03.22`.
+fn parse_queue_entry_time(time: &str) -> Option<i64> {
+    let (date, time) = time.split_once('-')?;
+    let mut date = date.split('.').map(|part| part.parse::<i64>().ok());
+    let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+    let mut time = time.split('.').map(|part| part.parse::<i64>().ok());
+    let (hour, minute, second) = (time.next()??, time.next()??, time.next()??);
+    // The game reports 0001.01.01-00.00.00 when the party isn't queuing.
+    if year < 1970 || !(1..=12).contains(&month) {
+        return None;
     }
+    let days = days_from_civil(year, month, day);
+    Some(days * 86400 + hour * 3600 + minute * 60 + second)
+}
+
+/// Days since 1970-01-01 of a date in the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = (month + 9) % 12;
+    let day_of_year = (153 * month + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
 }
 
 fn determine_status(presence: &ParsedPresence) -> GameStateStatus {
     match presence.session_loop_state.as_str() {
         "MENUS" => GameStateStatus::Menu {
             in_queue: presence.party_state == "MATCHMAKING",
+            idle: presence.is_idle,
         },
         "PREGAME" => GameStateStatus::PreGame,
         "INGAME" => GameStateStatus::InGame,
-        _ => panic!("Invalid session loop state."),
+        state => GameStateStatus::Unknown(state.to_owned()),
     }
 }
 
 fn determine_game_mode(presence: &ParsedPresence) -> GameMode {
-    if presence.party_state == "CUSTOM_GAME_SETUP" {
-        return GameMode::CustomGame;
-    }
-    match presence.queue_id.as_str() {
-        "competitive" => GameMode::Competitive,
-        "unrated" => GameMode::Unrated,
-        "spikerush" => GameMode::SpikeRush,
-        "deathmatch" => GameMode::Deathmatch,
-        "onefa" => GameMode::Replication,
-        _ => GameMode::Unknown,
+    if presence.party_state == "CUSTOM_GAME_SETUP" || presence.provisioning_flow == "CustomGame" {
+        return GameMode::new(CUSTOM_QUEUE);
+    }
+    GameMode::new(&presence.queue_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_days_since_the_epoch() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(1969, 12, 31), -1);
+        assert_eq!(days_from_civil(2000, 2, 29), 11016);
+        assert_eq!(days_from_civil(2000, 3, 1), 11017);
+        assert_eq!(days_from_civil(2024, 2, 29), 19782);
+        assert_eq!(days_from_civil(1600, 1, 1), -135140);
+    }
+
+    #[test]
+    fn parses_queue_entry_time() {
+        assert_eq!(
+            parse_queue_entry_time("2022.05.21-14.03.22"),
+            Some(1653141802)
+        );
+        assert_eq!(parse_queue_entry_time("1970.01.01-00.00.00"), Some(0));
+    }
+
+    #[test]
+    fn ignores_queue_entry_time_while_not_queuing() {
+        assert_eq!(parse_queue_entry_time("0001.01.01-00.00.00"), None);
+    }
+
+    #[test]
+    fn rejects_malformed_queue_entry_time() {
+        for time in [
+            "",
+            "2022.05.21",
+            "2022.05.21-14.03",
+            "2022.13.01-00.00.00",
+            "2022.00.01-00.00.00",
+            "2022.05.xx-14.03.22",
+        ] {
+            assert_eq!(parse_queue_entry_time(time), None, "{}", time);
+        }
+    }
+
+    fn presence(session_loop_state: &str) -> ParsedPresence {
+        ParsedPresence {
+            session_loop_state: session_loop_state.to_owned(),
+            party_state: "DEFAULT".to_owned(),
+            queue_id: "unrated".to_owned(),
+            match_map: "/Game/Maps/Ascent/Ascent".to_owned(),
+            ..Default::default()
+        }
+    }
+
+    fn queuing(queue_entry_time: &str) -> ParsedPresence {
+        ParsedPresence {
+            party_state: "MATCHMAKING".to_owned(),
+            queue_entry_time: Some(queue_entry_time.to_owned()),
+            ..presence("MENUS")
+        }
+    }
+
+    fn in_game(ally: u32, enemy: u32) -> ParsedPresence {
+        ParsedPresence {
+            party_owner_match_score_ally_team: ally,
+            party_owner_match_score_enemy_team: enemy,
+            ..presence("INGAME")
+        }
+    }
+
+    /// Sets the start of the current phase to a time `analyze` never produces, to tell whether
+    /// it restarts the timer.
+    fn mark(tracker: &mut PresenceTracker) {
+        tracker.started_at = Some(1);
+    }
+
+    #[test]
+    fn menu_has_no_timer() {
+        let mut tracker = PresenceTracker::default();
+        assert_eq!(tracker.analyze(&presence("MENUS")).started_at, None);
+        assert_eq!(tracker.analyze(&presence("REPLAY")).started_at, None);
+    }
+
+    #[test]
+    fn queue_starts_at_queue_entry_time() {
+        let mut tracker = PresenceTracker::default();
+        tracker.analyze(&presence("MENUS"));
+        let state = tracker.analyze(&queuing("2022.05.21-14.03.22"));
+        assert_eq!(state.started_at, Some(1653141802));
+        // The timer keeps running while the phase doesn't change.
+        let state = tracker.analyze(&queuing("2022.05.21-14.05.00"));
+        assert_eq!(state.started_at, Some(1653141802));
+    }
+
+    #[test]
+    fn queue_without_entry_time_starts_now() {
+        let before = unix_now();
+        let mut tracker = PresenceTracker::default();
+        let started_at = tracker.analyze(&queuing("0001.01.01-00.00.00")).started_at;
+        assert!(started_at.unwrap() >= before);
+    }
+
+    #[test]
+    fn phase_changes_restart_the_timer() {
+        let before = unix_now();
+        let mut tracker = PresenceTracker::default();
+        tracker.analyze(&queuing("2022.05.21-14.03.22"));
+        let started_at = tracker.analyze(&presence("PREGAME")).started_at;
+        assert!(started_at.unwrap() >= before);
+        mark(&mut tracker);
+        assert_eq!(tracker.analyze(&presence("PREGAME")).started_at, Some(1));
+        assert!(tracker.analyze(&in_game(0, 0)).started_at.unwrap() >= before);
+        assert_eq!(tracker.analyze(&presence("MENUS")).started_at, None);
+        let idle = ParsedPresence {
+            is_idle: true,
+            ..presence("MENUS")
+        };
+        assert!(tracker.analyze(&idle).started_at.unwrap() >= before);
+    }
+
+    #[test]
+    fn rounds_keep_the_match_timer() {
+        let mut tracker = PresenceTracker::default();
+        tracker.analyze(&in_game(0, 0));
+        mark(&mut tracker);
+        assert_eq!(tracker.analyze(&in_game(1, 0)).started_at, Some(1));
+        assert_eq!(tracker.analyze(&in_game(1, 1)).started_at, Some(1));
+    }
+
+    #[test]
+    fn score_reset_restarts_the_match_timer() {
+        let mut tracker = PresenceTracker::default();
+        tracker.analyze(&in_game(3, 2));
+        mark(&mut tracker);
+        assert_ne!(tracker.analyze(&in_game(0, 0)).started_at, Some(1));
+    }
+
+    #[test]
+    fn map_change_restarts_the_match_timer() {
+        let mut tracker = PresenceTracker::default();
+        tracker.analyze(&in_game(3, 2));
+        mark(&mut tracker);
+        let other_map = ParsedPresence {
+            match_map: "/Game/Maps/Pitt/Pitt".to_owned(),
+            ..in_game(3, 2)
+        };
+        assert_ne!(tracker.analyze(&other_map).started_at, Some(1));
+    }
+
+    #[test]
+    fn names_custom_teams_independently_of_their_side() {
+        for (team, expected) in [
+            ("TeamOne", Some(CustomTeam::TeamA)),
+            ("TeamTwo", Some(CustomTeam::TeamB)),
+            ("TeamSpectate", Some(CustomTeam::Spectators)),
+            ("TeamTwoCoaches", Some(CustomTeam::Coaches)),
+            ("TeamThree", None),
+        ] {
+            let presence = ParsedPresence {
+                queue_id: "custom".to_owned(),
+                custom_game_team: Some(team.to_owned()),
+                ..presence("MENUS")
+            };
+            let custom = analyze_presence(&presence).custom.unwrap();
+            assert_eq!(custom.team, expected, "{}", team);
+        }
+        assert_eq!(CustomTeam::TeamA.role(), "Team A");
+        assert_eq!(CustomTeam::TeamB.role(), "Team B");
+    }
+
+    #[test]
+    fn warns_once_per_unknown_state() {
+        let mut tracker = PresenceTracker::default();
+        assert!(tracker.warn_unknown_state("REPLAY"));
+        tracker.analyze(&presence("REPLAY"));
+        assert!(!tracker.warn_unknown_state("REPLAY"));
+        tracker.analyze(&presence("MENUS"));
+        assert!(tracker.warn_unknown_state("SPECTATING"));
+        assert!(!tracker.warn_unknown_state("SPECTATING"));
     }
 }
@@ -0,0 +1,138 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use super::table::{DataTable, TableError, TableFile};
+
+const DEFAULT_QUEUES: &str = include_str!("data/queues.toml");
+
+/// Queue id used for custom games, which don't report a queue of their own.
+pub const CUSTOM_QUEUE: &str = "custom";
+
+static QUEUES: DataTable<QueueFile> = DataTable::new("queue registry", DEFAULT_QUEUES);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueueInfo {
+    pub name: String,
+    pub activity: String,
+    pub category: QueueCategory,
+    pub rounds: bool,
+    pub team_size: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QueueCategory {
+    Standard,
+    Competitive,
+    Deathmatch,
+    Arcade,
+    Custom,
+    Unknown,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct QueueFile {
+    #[serde(default)]
+    queue: Vec<QueueEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct QueueEntry {
+    id: String,
+    name: String,
+    activity: String,
+    category: QueueCategory,
+    rounds: bool,
+    team_size: u32,
+}
+
+impl TableFile for QueueFile {
+    type Entry = QueueInfo;
+
+    fn into_entries(self) -> Vec<(String, QueueInfo)> {
+        self.queue
+            .into_iter()
+            .map(|entry| {
+                let info = QueueInfo {
+                    name: entry.name,
+                    activity: entry.activity,
+                    category: entry.category,
+                    rounds: entry.rounds,
+                    team_size: entry.team_size,
+                };
+                (entry.id, info)
+            })
+            .collect()
+    }
+}
+
+/// Loads the queue registry, letting entries from `overrides` replace or extend the bundled
+/// queues.
+///
+/// Has to be called before the first queue is looked up, later calls are ignored.
+pub fn init(overrides: Option<&Path>) -> Result<(), TableError> {
+    QUEUES.init(overrides)
+}
+
+pub fn lookup_queue(queue_id: &str) -> QueueInfo {
+    match QUEUES.get(queue_id) {
+        Some(info) => info.clone(),
+        None => {
+            let name = if queue_id.is_empty() {
+                "Unknown".to_owned()
+            } else {
+                queue_id.to_owned()
+            };
+            QueueInfo {
+                activity: format!("Playing {}", name),
+                name,
+                category: QueueCategory::Unknown,
+                rounds: true,
+                team_size: 5,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::valorant::table::parse;
+
+    #[test]
+    fn bundled_queues_parse() {
+        let queues = parse::<QueueFile>(DEFAULT_QUEUES).unwrap();
+        assert_eq!(
+            queues["premier"],
+            QueueInfo {
+                name: "Premier".to_owned(),
+                activity: "Playing Premier".to_owned(),
+                category: QueueCategory::Competitive,
+                rounds: true,
+                team_size: 5,
+            }
+        );
+        assert_eq!(queues["swiftplay"].name, "Swiftplay");
+        assert!(queues["swiftplay"].rounds);
+        assert!(!queues["deathmatch"].rounds);
+        assert_eq!(queues[CUSTOM_QUEUE].category, QueueCategory::Custom);
+    }
+
+    #[test]
+    fn rejects_incomplete_queues() {
+        assert!(parse::<QueueFile>("[[queue]]\nid = \"new\"\nname = \"New\"\n").is_err());
+    }
+
+    #[test]
+    fn unknown_queues_are_named_after_their_id() {
+        let info = lookup_queue("newmode");
+        assert_eq!(info.name, "newmode");
+        assert_eq!(info.activity, "Playing newmode");
+        assert_eq!(info.category, QueueCategory::Unknown);
+        assert_eq!(lookup_queue("").name, "Unknown");
+        assert_eq!(lookup_queue("competitive").name, "Competitive");
+    }
+}
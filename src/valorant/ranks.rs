@@ -0,0 +1,49 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rank {
+    pub tier: u32,
+    pub name: String,
+    pub icon_key: String,
+}
+
+/// Competitive tiers by their number, with the key of their icon in the Discord application.
+const TIERS: &[(u32, &str, &str)] = &[
+    (3, "Iron 1", "iron_1"),
+    (4, "Iron 2", "iron_2"),
+    (5, "Iron 3", "iron_3"),
+    (6, "Bronze 1", "bronze_1"),
+    (7, "Bronze 2", "bronze_2"),
+    (8, "Bronze 3", "bronze_3"),
+    (9, "Silver 1", "silver_1"),
+    (10, "Silver 2", "silver_2"),
+    (11, "Silver 3", "silver_3"),
+    (12, "Gold 1", "gold_1"),
+    (13, "Gold 2", "gold_2"),
+    (14, "Gold 3", "gold_3"),
+    (15, "Platinum 1", "platinum_1"),
+    (16, "Platinum 2", "platinum_2"),
+    (17, "Platinum 3", "platinum_3"),
+    (18, "Diamond 1", "diamond_1"),
+    (19, "Diamond 2", "diamond_2"),
+    (20, "Diamond 3", "diamond_3"),
+    (21, "Ascendant 1", "ascendant_1"),
+    (22, "Ascendant 2", "ascendant_2"),
+    (23, "Ascendant 3", "ascendant_3"),
+    (24, "Immortal 1", "immortal_1"),
+    (25, "Immortal 2", "immortal_2"),
+    (26, "Immortal 3", "immortal_3"),
+    (27, "Radiant", "radiant"),
+];
+
+/// Looks up a competitive tier, unranked players and unknown tiers have no rank.
+pub fn determine_rank(tier: u32) -> Option<Rank> {
+    TIERS
+        .iter()
+        .find(|(number, _, _)| *number == tier)
+        .map(|(tier, name, icon_key)| Rank {
+            tier: *tier,
+            name: (*name).to_owned(),
+            icon_key: (*icon_key).to_owned(),
+        })
+}
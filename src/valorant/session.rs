@@ -7,3 +7,27 @@ pub struct SessionResponse {
     pub loaded: bool,
     pub puuid: String,
 }
+
+/// The player whose presence is shown, taken from a loaded chat session.
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub puuid: String,
+    pub game_name: String,
+    pub game_tag: String,
+}
+
+impl Session {
+    pub fn riot_id(&self) -> String {
+        format!("{}#{}", self.game_name, self.game_tag)
+    }
+}
+
+impl From<SessionResponse> for Session {
+    fn from(response: SessionResponse) -> Self {
+        Session {
+            puuid: response.puuid,
+            game_name: response.game_name,
+            game_tag: response.game_tag,
+        }
+    }
+}
@@ -0,0 +1,309 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use reqwest::Client;
+use serde::Deserialize;
+use tokio::sync::mpsc::Sender;
+use tracing::{debug, error, info, info_span, warn, Instrument};
+
+use crate::{
+    error::{Error, Result},
+    lockfile::{get_lockfile_credentials, RiotCredentials, RiotDirNotFoundError},
+};
+
+use super::{
+    api::RiotApi,
+    decode::DecodeReporter,
+    game_state::GameState,
+    presence_analyzer::PresenceTracker,
+    session::Session,
+    websocket::{receive_websocket_events, SUBSCRIBED_EVENTS},
+};
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// Sessions which lasted at least this long reset the backoff.
+const STABLE_SESSION: Duration = Duration::from_secs(30);
+
+const READY_INITIAL_DELAY: Duration = Duration::from_millis(500);
+const READY_MAX_DELAY: Duration = Duration::from_secs(5);
+/// How long a starting client may take until it offers the events we subscribe to.
+const READY_DEADLINE: Duration = Duration::from_secs(120);
+
+pub enum ClientEvent {
+    Connected {
+        session: Session,
+        api: Arc<dyn RiotApi>,
+    },
+    State(Box<GameState>),
+    Disconnected,
+}
+
+/// Keeps a websocket session to the Riot Client alive until the client exits.
+///
+/// Every attempt re-reads the lockfile, so a restarted client is picked up with its new
+/// port and password.
+pub async fn supervise(sender: Sender<ClientEvent>, riot_dir: PathBuf, dump_dir: Option<PathBuf>) {
+    let mut backoff = INITIAL_BACKOFF;
+    let mut reporter = DecodeReporter::new(dump_dir);
+    let mut tracker = PresenceTracker::default();
+    for attempt in 1u64.. {
+        let started = Instant::now();
+        let result = run_session(&sender, &riot_dir, &mut reporter, &mut tracker)
+            .instrument(info_span!("session", attempt))
+            .await;
+        let retryable = match result {
+            Ok(()) => {
+                info!("Websocket connection closed.");
+                true
+            }
+            Err(e) => {
+                warn!("Websocket session failed: {}", e);
+                e.is_retryable()
+            }
+        };
+        if sender.send(ClientEvent::Disconnected).await.is_err() {
+            return;
+        }
+        if !retryable {
+            error!("Not reconnecting, as trying again won't help.");
+            return;
+        }
+        if !riot_dir.join("lockfile").exists() {
+            info!("Riot Client exited.");
+            return;
+        }
+        if started.elapsed() >= STABLE_SESSION {
+            backoff = INITIAL_BACKOFF;
+        }
+        info!("Reconnecting in {}s...", backoff.as_secs());
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+async fn run_session(
+    sender: &Sender<ClientEvent>,
+    riot_dir: &Path,
+    reporter: &mut DecodeReporter,
+    tracker: &mut PresenceTracker,
+) -> Result<()> {
+    // The lockfile watcher doesn't see a directory which was removed come back, e.g. when the
+    // Wine prefix moved, so reconnecting can't help.
+    if !riot_dir.is_dir() {
+        return Err(RiotDirNotFoundError {
+            tried: vec![riot_dir.to_path_buf()],
+        }
+        .into());
+    }
+    let creds = get_lockfile_credentials(riot_dir).await?;
+    // The local client uses a self-signed certificate.
+    let client = Client::builder()
+        .danger_accept_invalid_certs(true)
+        .build()?;
+    wait_until_server_ready(&client, &ReadinessProbe::new(&creds), riot_dir)
+        .instrument(info_span!("readiness", port = creds.port))
+        .await?;
+    receive_websocket_events(sender, creds, &client, reporter, tracker).await
+}
+
+/// Where the client is asked whether it is ready, and how patiently.
+struct ReadinessProbe {
+    help_url: String,
+    password: String,
+    initial_delay: Duration,
+    max_delay: Duration,
+    deadline: Duration,
+}
+
+impl ReadinessProbe {
+    fn new(creds: &RiotCredentials) -> ReadinessProbe {
+        ReadinessProbe {
+            help_url: format!("https://127.0.0.1:{}/help", creds.port),
+            password: creds.password.clone(),
+            initial_delay: READY_INITIAL_DELAY,
+            max_delay: READY_MAX_DELAY,
+            deadline: READY_DEADLINE,
+        }
+    }
+}
+
+/// Polls the client until it offers every event we subscribe to.
+///
+/// Gives up once the lockfile disappears or the client doesn't get ready within the deadline
+/// of the probe.
+async fn wait_until_server_ready(
+    client: &Client,
+    probe: &ReadinessProbe,
+    riot_dir: &Path,
+) -> Result<()> {
+    let deadline = Instant::now() + probe.deadline;
+    let mut delay = probe.initial_delay;
+    loop {
+        match missing_events(client, probe).await {
+            Ok(missing) if missing.is_empty() => return Ok(()),
+            Ok(missing) => debug!(?missing, "Server is not ready yet, events are missing."),
+            Err(e) => debug!("Server is not ready yet: {}", e),
+        }
+        if !riot_dir.join("lockfile").exists() {
+            return Err(Error::RiotClient("exited before it was ready".to_owned()));
+        }
+        if Instant::now() + delay > deadline {
+            return Err(Error::RiotClient(format!(
+                "not ready after {}s",
+                probe.deadline.as_secs()
+            )));
+        }
+        tokio::time::sleep(delay).await;
+        delay = (delay * 2).min(probe.max_delay);
+    }
+}
+
+#[derive(Deserialize)]
+struct HelpResponse {
+    events: HashMap<String, serde_json::Value>,
+}
+
+/// Asks the client which of the events we subscribe to it doesn't offer yet.
+async fn missing_events(client: &Client, probe: &ReadinessProbe) -> Result<Vec<&'static str>> {
+    let help: HelpResponse = client
+        .get(&probe.help_url)
+        .basic_auth("riot", Some(&probe.password))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    Ok(SUBSCRIBED_EVENTS
+        .iter()
+        .copied()
+        .filter(|event| !help.events.contains_key(*event))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicU32, Ordering},
+        Mutex,
+    };
+
+    use serde_json::json;
+    use tempfile::TempDir;
+    use tokio::{sync::mpsc::channel, time::timeout};
+
+    use super::*;
+    use crate::valorant::testing::serve;
+
+    /// A probe of the stand-in at `url` which gives up after `deadline` milliseconds.
+    fn probe(url: &str, deadline: u64) -> ReadinessProbe {
+        ReadinessProbe {
+            help_url: format!("{}/help", url),
+            password: "secret".to_owned(),
+            initial_delay: Duration::from_millis(10),
+            max_delay: Duration::from_millis(20),
+            deadline: Duration::from_millis(deadline),
+        }
+    }
+
+    /// The `/help` of a client which offers the events.
+    fn help(events: &[&str]) -> (u16, String) {
+        let events: serde_json::Map<_, _> = events
+            .iter()
+            .map(|event| (event.to_string(), json!("")))
+            .collect();
+        (200, json!({ "events": events }).to_string())
+    }
+
+    /// A Riot Client directory which still has a lockfile.
+    fn riot_dir() -> TempDir {
+        let dir = TempDir::new().unwrap();
+        std::fs::write(dir.path().join("lockfile"), "").unwrap();
+        dir
+    }
+
+    #[tokio::test]
+    async fn asks_the_client_for_the_missing_events() {
+        let offered = Arc::new(Mutex::new(Vec::new()));
+        let events = offered.clone();
+        let (url, _) = serve(move |request, head| {
+            assert_eq!(request, "GET /help");
+            assert!(
+                head.contains("authorization: Basic cmlvdDpzZWNyZXQ="),
+                "{}",
+                head
+            );
+            help(&events.lock().unwrap())
+        })
+        .await;
+        let probe = probe(&url, 1000);
+        let missing = missing_events(&Client::new(), &probe).await.unwrap();
+        assert_eq!(missing, SUBSCRIBED_EVENTS);
+        offered.lock().unwrap().extend_from_slice(SUBSCRIBED_EVENTS);
+        offered.lock().unwrap().push("OnJsonApiEvent");
+        let missing = missing_events(&Client::new(), &probe).await.unwrap();
+        assert!(missing.is_empty());
+    }
+
+    #[tokio::test]
+    async fn waits_until_the_client_offers_every_event() {
+        let polls = AtomicU32::new(0);
+        let (url, requests) = serve(move |_, _| match polls.fetch_add(1, Ordering::SeqCst) {
+            0 => (503, String::new()),
+            1 => help(&[]),
+            _ => help(SUBSCRIBED_EVENTS),
+        })
+        .await;
+        let dir = riot_dir();
+        wait_until_server_ready(&Client::new(), &probe(&url, 1000), dir.path())
+            .await
+            .unwrap();
+        assert_eq!(requests.lock().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_the_deadline() {
+        let (url, requests) = serve(|_, _| help(&[])).await;
+        let dir = riot_dir();
+        let started = Instant::now();
+        let result = wait_until_server_ready(&Client::new(), &probe(&url, 300), dir.path()).await;
+        assert!(matches!(result, Err(Error::RiotClient(e)) if e.starts_with("not ready")));
+        assert!(started.elapsed() < Duration::from_secs(1));
+        // Without the cap of 20ms, the delays of 10, 20, 40, 80 and 160ms leave 5 polls.
+        assert!(requests.lock().unwrap().len() > 8);
+    }
+
+    #[tokio::test]
+    async fn gives_up_when_the_lockfile_disappears() {
+        let (url, requests) = serve(|_, _| help(&[])).await;
+        let dir = TempDir::new().unwrap();
+        let result = wait_until_server_ready(&Client::new(), &probe(&url, 1000), dir.path()).await;
+        assert!(matches!(result, Err(Error::RiotClient(e)) if e.contains("exited")));
+        assert_eq!(requests.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn gives_up_when_the_riot_dir_is_gone() {
+        let (sender, mut receiver) = channel(8);
+        let riot_dir = std::env::temp_dir().join("valorant-rpc-missing-riot-dir");
+        timeout(Duration::from_secs(5), supervise(sender, riot_dir, None))
+            .await
+            .expect("the supervisor kept reconnecting");
+        assert!(matches!(
+            receiver.recv().await,
+            Some(ClientEvent::Disconnected)
+        ));
+        assert!(receiver.recv().await.is_none());
+    }
+
+    #[test]
+    fn only_transient_errors_are_retried() {
+        assert!(Error::RiotClient("not ready".to_owned()).is_retryable());
+        assert!(!Error::RiotDirNotFound(RiotDirNotFoundError { tried: Vec::new() }).is_retryable());
+        assert!(!Error::Usage("unknown argument".to_owned()).is_retryable());
+    }
+}
@@ -0,0 +1,122 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt::Display,
+    fs, io,
+    marker::PhantomData,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use serde::de::DeserializeOwned;
+use tracing::info;
+
+/// A TOML file of game data, like the bundled map table.
+pub trait TableFile: DeserializeOwned {
+    type Entry;
+
+    /// The entries of the file by the id the game reports.
+    fn into_entries(self) -> Vec<(String, Self::Entry)>;
+}
+
+/// Game data which is bundled with the app and can be extended by a file of the user, so new
+/// content doesn't need an update.
+pub struct DataTable<F: TableFile> {
+    /// What the table holds, e.g. "map table".
+    name: &'static str,
+    bundled: &'static str,
+    entries: OnceLock<HashMap<String, F::Entry>>,
+    file: PhantomData<fn() -> F>,
+}
+
+impl<F: TableFile> DataTable<F> {
+    pub const fn new(name: &'static str, bundled: &'static str) -> DataTable<F> {
+        DataTable {
+            name,
+            bundled,
+            entries: OnceLock::new(),
+            file: PhantomData,
+        }
+    }
+
+    /// Loads the table, letting entries from `overrides` replace or extend the bundled ones.
+    ///
+    /// Has to be called before the first lookup, later calls are ignored.
+    pub fn init(&self, overrides: Option<&Path>) -> Result<(), TableError> {
+        let error = |path: Option<&Path>, kind| TableError {
+            name: self.name,
+            path: path.map(Path::to_path_buf),
+            kind,
+        };
+        let mut entries =
+            parse::<F>(self.bundled).map_err(|e| error(None, TableErrorKind::Parse(e)))?;
+        if let Some(path) = overrides {
+            let content =
+                fs::read_to_string(path).map_err(|e| error(Some(path), TableErrorKind::Io(e)))?;
+            let overrides =
+                parse::<F>(&content).map_err(|e| error(Some(path), TableErrorKind::Parse(e)))?;
+            info!(
+                "Loaded {} entries of the {} from {}",
+                overrides.len(),
+                self.name,
+                path.display()
+            );
+            entries.extend(overrides);
+        }
+        let _ = self.entries.set(entries);
+        Ok(())
+    }
+
+    pub fn get(&self, id: &str) -> Option<&F::Entry> {
+        self.entries
+            .get_or_init(|| parse::<F>(self.bundled).expect("bundled table is invalid"))
+            .get(id)
+    }
+}
+
+pub fn parse<F: TableFile>(content: &str) -> Result<HashMap<String, F::Entry>, toml::de::Error> {
+    let file: F = toml::from_str(content)?;
+    Ok(file.into_entries().into_iter().collect())
+}
+
+#[derive(Debug)]
+pub struct TableError {
+    /// What the table holds, e.g. "map table".
+    pub name: &'static str,
+    /// The file of the user, `None` for the bundled table.
+    pub path: Option<PathBuf>,
+    pub kind: TableErrorKind,
+}
+
+#[derive(Debug)]
+pub enum TableErrorKind {
+    Io(io::Error),
+    Parse(toml::de::Error),
+}
+
+impl Display for TableError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (&self.path, &self.kind) {
+            (None, TableErrorKind::Io(e)) => {
+                f.write_fmt(format_args!("unable to read bundled {}: {}", self.name, e))
+            }
+            (None, TableErrorKind::Parse(e)) => {
+                f.write_fmt(format_args!("bundled {} is invalid: {}", self.name, e))
+            }
+            (Some(path), TableErrorKind::Io(e)) => f.write_fmt(format_args!(
+                "unable to read {} {}: {}",
+                self.name,
+                path.display(),
+                e
+            )),
+            (Some(path), TableErrorKind::Parse(e)) => f.write_fmt(format_args!(
+                "invalid {} {}: {}",
+                self.name,
+                path.display(),
+                e
+            )),
+        }
+    }
+}
+
+impl Error for TableError {}
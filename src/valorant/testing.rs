@@ -0,0 +1,158 @@
+//! Stand-ins for the Riot Client, shared by the tests of the modules which talk to it.
+
+use std::sync::{
+    atomic::{AtomicU32, Ordering},
+    Arc, Mutex,
+};
+
+use async_trait::async_trait;
+use base64::encode;
+use serde_json::json;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+
+use super::{
+    api::{AgentSelection, RiotApi},
+    game_state::{GameMode, GameState, GameStateStatus, Party, Scores},
+    maps::determine_map,
+    presence::Presence,
+};
+use crate::error::{Error, Result};
+
+/// Stands in for the Riot Client and the game servers.
+///
+/// Knows the presences of a few friends as their name, tag and party, records the parties
+/// it joins, and counts how often it is asked for the selected agent.
+#[derive(Default)]
+pub struct StandInApi {
+    pub friends: Vec<(&'static str, &'static str, Option<&'static str>)>,
+    pub joined: Mutex<Vec<String>>,
+    /// The agent the player selected, if any.
+    pub character_id: Option<&'static str>,
+    /// Whether asking for the selected agent fails.
+    pub fail: bool,
+    pub pregame_calls: AtomicU32,
+    pub coregame_calls: AtomicU32,
+}
+
+impl StandInApi {
+    pub fn pregame_calls(&self) -> u32 {
+        self.pregame_calls.load(Ordering::SeqCst)
+    }
+
+    pub fn coregame_calls(&self) -> u32 {
+        self.coregame_calls.load(Ordering::SeqCst)
+    }
+
+    fn selection(&self, locked: bool) -> Result<Option<AgentSelection>> {
+        if self.fail {
+            return Err(Error::RiotClient("unavailable".to_owned()));
+        }
+        Ok(self.character_id.map(|id| AgentSelection {
+            character_id: id.to_owned(),
+            locked,
+        }))
+    }
+}
+
+#[async_trait]
+impl RiotApi for StandInApi {
+    async fn presences(&self) -> Result<Vec<Presence>> {
+        Ok(self
+            .friends
+            .iter()
+            .map(|(name, tag, party_id)| Presence {
+                puuid: format!("{}-puuid", name),
+                product: "valorant".to_owned(),
+                private: encode(json!({ "partyId": party_id }).to_string()),
+                game_name: (*name).to_owned(),
+                game_tag: (*tag).to_owned(),
+            })
+            .collect())
+    }
+
+    async fn join_party(&self, party_id: &str) -> Result<()> {
+        self.joined.lock().unwrap().push(party_id.to_owned());
+        Ok(())
+    }
+
+    async fn pregame_agent(&self) -> Result<Option<AgentSelection>> {
+        self.pregame_calls.fetch_add(1, Ordering::SeqCst);
+        self.selection(false)
+    }
+
+    async fn coregame_agent(&self) -> Result<Option<AgentSelection>> {
+        self.coregame_calls.fetch_add(1, Ordering::SeqCst);
+        self.selection(true)
+    }
+}
+
+/// Serves the local client or the game servers over plain http, recording every request.
+///
+/// `respond` gets the request line without the version, e.g. `GET /path`, and the whole head.
+pub async fn serve<F>(respond: F) -> (String, Arc<Mutex<Vec<String>>>)
+where
+    F: Fn(&str, &str) -> (u16, String) + Send + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let recorded = requests.clone();
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut head = Vec::new();
+            let mut buffer = [0; 1024];
+            while !head.windows(4).any(|window| window == b"\r\n\r\n") {
+                let read = stream.read(&mut buffer).await.unwrap();
+                if read == 0 {
+                    break;
+                }
+                head.extend_from_slice(&buffer[..read]);
+            }
+            let head = String::from_utf8_lossy(&head).into_owned();
+            let line = head.lines().next().unwrap_or_default();
+            let request = line.rsplit_once(' ').map_or(line, |(request, _)| request);
+            recorded.lock().unwrap().push(request.to_owned());
+            let (status, body) = respond(request, &head);
+            let response = format!(
+                "HTTP/1.1 {} Stand-In\r\ncontent-type: application/json\r\n\
+                 content-length: {}\r\nconnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            );
+            stream.write_all(response.as_bytes()).await.unwrap();
+        }
+    });
+    (url, requests)
+}
+
+/// The state of a solo player in `status`, playing the queue on the map.
+pub fn game_state(status: GameStateStatus, queue_id: &str, map: &str) -> GameState {
+    GameState {
+        game_mode: GameMode::new(queue_id),
+        status,
+        scores: Scores {
+            ally_team: 0,
+            enemy_team: 0,
+        },
+        party: Party {
+            id: None,
+            size: 1,
+            max_size: 5,
+            open: false,
+        },
+        map: determine_map(map),
+        rank: None,
+        agent: None,
+        custom: None,
+        started_at: None,
+    }
+}
+
+pub fn menu(in_queue: bool, idle: bool) -> GameStateStatus {
+    GameStateStatus::Menu { in_queue, idle }
+}
@@ -0,0 +1,66 @@
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    hash::Hash,
+    time::{Duration, Instant},
+};
+
+use tracing::warn;
+
+/// Warns about each kind of error only once per interval, so an error which repeats on every
+/// event doesn't flood the output. The warnings which are held back are counted instead.
+pub struct WarningThrottle<K> {
+    interval: Duration,
+    last_warning: HashMap<K, Instant>,
+    suppressed: HashMap<K, u32>,
+}
+
+impl<K: Copy + Eq + Hash> WarningThrottle<K> {
+    pub fn new(interval: Duration) -> WarningThrottle<K> {
+        WarningThrottle {
+            interval,
+            last_warning: HashMap::new(),
+            suppressed: HashMap::new(),
+        }
+    }
+
+    /// Warns about an error of `kind` which happened at `now`, returning how many similar
+    /// errors were suppressed before it, or `None` if it is suppressed itself.
+    pub fn warn(&mut self, kind: K, now: Instant, message: impl Display) -> Option<u32> {
+        if let Some(last) = self.last_warning.get(&kind) {
+            if now.duration_since(*last) < self.interval {
+                *self.suppressed.entry(kind).or_default() += 1;
+                return None;
+            }
+        }
+        self.last_warning.insert(kind, now);
+        let suppressed = self.suppressed.remove(&kind).unwrap_or_default();
+        if suppressed > 0 {
+            warn!("{} ({} similar errors suppressed)", message, suppressed);
+        } else {
+            warn!("{}", message);
+        }
+        Some(suppressed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INTERVAL: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn counts_the_warnings_it_holds_back() {
+        let mut throttle = WarningThrottle::new(INTERVAL);
+        let start = Instant::now();
+        assert_eq!(throttle.warn((), start, "failed"), Some(0));
+        assert_eq!(throttle.warn((), start + INTERVAL / 2, "failed"), None);
+        assert_eq!(throttle.warn((), start + INTERVAL / 2, "failed"), None);
+        assert_eq!(throttle.warn((), start + INTERVAL, "failed"), Some(2));
+        // The interval starts over with the last warning.
+        let later = start + INTERVAL + INTERVAL / 2;
+        assert_eq!(throttle.warn((), later, "failed"), None);
+        assert_eq!(throttle.warn((), start + INTERVAL * 2, "failed"), Some(1));
+    }
+}
@@ -0,0 +1,415 @@
+use std::{error::Error as StdError, fmt::Display, sync::Mutex};
+
+use base64::encode;
+use futures::{
+    stream::{SplitSink, SplitStream},
+    SinkExt, StreamExt,
+};
+use http::{header::AUTHORIZATION, HeaderValue, Request};
+use native_tls::TlsConnector;
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{json, Value};
+use tokio::{
+    net::TcpStream,
+    sync::{
+        mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+        Mutex as AsyncMutex,
+    },
+};
+use tokio_tungstenite::{
+    connect_async_tls_with_config,
+    tungstenite::{self, client::IntoClientRequest, error::TlsError, Message},
+    Connector, MaybeTlsStream, WebSocketStream,
+};
+use tracing::debug;
+
+use crate::{error::Result, lockfile::RiotCredentials};
+
+const SUBSCRIBE: u8 = 5;
+const UNSUBSCRIBE: u8 = 6;
+const EVENT: u8 = 8;
+
+/// Catch-all event, subscribing to it delivers every change of the local api.
+pub const JSON_API_EVENT: &str = "OnJsonApiEvent";
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// An event of the local api, like a changed presence or a new entitlements token.
+#[derive(Debug, Clone)]
+pub struct Event<T> {
+    /// The event which was subscribed to, e.g. `OnJsonApiEvent_chat_v4_presences`.
+    pub subscription: String,
+    /// The endpoint which changed, e.g. `/chat/v4/presences`.
+    pub uri: String,
+    /// `Create`, `Update` or `Delete`.
+    pub event_type: String,
+    pub data: T,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EventPayload {
+    uri: String,
+    event_type: String,
+    data: Value,
+}
+
+/// Selects the events a route receives.
+#[derive(Debug, Clone)]
+pub struct EventFilter {
+    uri: String,
+    event_type: Option<String>,
+}
+
+impl EventFilter {
+    pub fn uri(uri: &str) -> EventFilter {
+        EventFilter {
+            uri: uri.to_owned(),
+            event_type: None,
+        }
+    }
+
+    /// Only lets events of this type through, like `Update`.
+    pub fn event_type(mut self, event_type: &str) -> EventFilter {
+        self.event_type = Some(event_type.to_owned());
+        self
+    }
+
+    fn matches(&self, event: &Event<Value>) -> bool {
+        self.uri == event.uri
+            && self
+                .event_type
+                .as_ref()
+                .is_none_or(|event_type| *event_type == event.event_type)
+    }
+}
+
+/// Decodes an event and hands it to its receiver, returning whether the receiver is still there.
+type Route = Box<dyn FnMut(&Event<Value>) -> bool + Send>;
+
+/// A client for the WAMP 1.0 dialect the Riot Client speaks on its local websocket.
+///
+/// Events are only read while [`WampClient::run`] is polled. Routes and subscriptions can be
+/// added at any time, events which arrive before a route exists aren't delivered to it.
+pub struct WampClient {
+    write: AsyncMutex<SplitSink<Socket, Message>>,
+    read: AsyncMutex<SplitStream<Socket>>,
+    routes: Mutex<Vec<(EventFilter, Route)>>,
+    listeners: Mutex<Vec<UnboundedSender<Event<Value>>>>,
+}
+
+impl WampClient {
+    pub async fn connect(creds: &RiotCredentials) -> Result<WampClient> {
+        let request = build_request(creds)?;
+        let (socket, _) =
+            connect_async_tls_with_config(request, None, Some(build_ssl_config()?)).await?;
+        Ok(WampClient::new(socket))
+    }
+
+    fn new(socket: Socket) -> WampClient {
+        let (write, read) = socket.split();
+        WampClient {
+            write: AsyncMutex::new(write),
+            read: AsyncMutex::new(read),
+            routes: Mutex::new(Vec::new()),
+            listeners: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub async fn subscribe(&self, event: &str) -> Result<()> {
+        self.send(SUBSCRIBE, event).await
+    }
+
+    pub async fn unsubscribe(&self, event: &str) -> Result<()> {
+        self.send(UNSUBSCRIBE, event).await
+    }
+
+    async fn send(&self, opcode: u8, event: &str) -> Result<()> {
+        let message = json!([opcode, event]).to_string();
+        Ok(self.write.lock().await.send(Message::text(message)).await?)
+    }
+
+    /// Delivers the events matching `filter` decoded as `T`.
+    ///
+    /// Events which don't match `T` are delivered as errors, so they can be reported.
+    pub fn route<T>(
+        &self,
+        filter: EventFilter,
+    ) -> UnboundedReceiver<Result<Event<T>, EventDecodeError>>
+    where
+        T: DeserializeOwned + Send + 'static,
+    {
+        let (sender, receiver) = unbounded_channel();
+        let route: Route = Box::new(move |event| sender.send(decode_event(event)).is_ok());
+        self.routes
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push((filter, route));
+        receiver
+    }
+
+    /// Delivers every event of every subscription, undecoded.
+    pub fn events(&self) -> UnboundedReceiver<Event<Value>> {
+        let (sender, receiver) = unbounded_channel();
+        self.listeners
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(sender);
+        receiver
+    }
+
+    /// Reads events and dispatches them to the routes until the connection is closed.
+    pub async fn run(&self) -> Result<()> {
+        let mut read = self.read.lock().await;
+        while let Some(message) = read.next().await {
+            let text = match message? {
+                Message::Text(text) => text,
+                _ => continue,
+            };
+            // The client acknowledges subscriptions with an empty message.
+            if text.is_empty() {
+                continue;
+            }
+            match parse_event(&text) {
+                Ok(Some(event)) => self.dispatch(event),
+                Ok(None) => {}
+                Err(e) => debug!("Ignoring websocket message which isn't an event: {}", e),
+            }
+        }
+        Ok(())
+    }
+
+    fn dispatch(&self, event: Event<Value>) {
+        let mut routes = self.routes.lock().unwrap_or_else(|e| e.into_inner());
+        // Routes whose receiver is gone are dropped.
+        routes.retain_mut(|(filter, route)| !filter.matches(&event) || route(&event));
+        let mut listeners = self.listeners.lock().unwrap_or_else(|e| e.into_inner());
+        listeners.retain(|listener| listener.send(event.clone()).is_ok());
+    }
+}
+
+/// Parses a websocket message, returning `None` for messages which aren't events.
+fn parse_event(text: &str) -> serde_json::Result<Option<Event<Value>>> {
+    let message: Vec<Value> = serde_json::from_str(text)?;
+    match message.as_slice() {
+        [opcode, Value::String(subscription), payload] if *opcode == EVENT => {
+            let payload = EventPayload::deserialize(payload)?;
+            Ok(Some(Event {
+                subscription: subscription.clone(),
+                uri: payload.uri,
+                event_type: payload.event_type,
+                data: payload.data,
+            }))
+        }
+        _ => Ok(None),
+    }
+}
+
+fn decode_event<T: DeserializeOwned>(event: &Event<Value>) -> Result<Event<T>, EventDecodeError> {
+    match serde_path_to_error::deserialize(&event.data) {
+        Ok(data) => Ok(Event {
+            subscription: event.subscription.clone(),
+            uri: event.uri.clone(),
+            event_type: event.event_type.clone(),
+            data,
+        }),
+        Err(e) => Err(EventDecodeError {
+            uri: event.uri.clone(),
+            path: e.path().to_string(),
+            source: e.into_inner(),
+            payload: event.data.to_string(),
+        }),
+    }
+}
+
+fn build_request(creds: &RiotCredentials) -> Result<Request<()>> {
+    let host = format!("wss://127.0.0.1:{}", creds.port);
+    let mut request = host.into_client_request()?;
+    let basic_auth = encode(format!("riot:{}", creds.password));
+    let header = HeaderValue::from_str(&format!("Basic {}", basic_auth))
+        .map_err(|e| tungstenite::Error::HttpFormat(e.into()))?;
+    request.headers_mut().insert(AUTHORIZATION, header);
+    Ok(request)
+}
+
+fn build_ssl_config() -> Result<Connector> {
+    let connector = TlsConnector::builder()
+        .danger_accept_invalid_certs(true)
+        .build()
+        .map_err(|e| tungstenite::Error::Tls(TlsError::Native(e)))?;
+    Ok(Connector::NativeTls(connector))
+}
+
+/// The data of an event doesn't match the type of its route.
+#[derive(Debug)]
+pub struct EventDecodeError {
+    pub uri: String,
+    pub path: String,
+    pub source: serde_json::Error,
+    /// The data of the event, to attach it to bug reports.
+    pub payload: String,
+}
+
+impl Display for EventDecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!(
+            "unable to parse {} event at {}: {}",
+            self.uri, self.path, self.source
+        ))
+    }
+}
+
+impl StdError for EventDecodeError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        Some(&self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::{accept_async, connect_async};
+
+    use super::*;
+
+    const PRESENCES: &str = "OnJsonApiEvent_chat_v4_presences";
+
+    #[derive(Debug, Deserialize)]
+    struct Count {
+        count: u32,
+    }
+
+    fn event_frame(uri: &str, event_type: &str, data: Value) -> String {
+        let payload = json!({ "uri": uri, "eventType": event_type, "data": data });
+        json!([EVENT, PRESENCES, payload]).to_string()
+    }
+
+    fn event(uri: &str, event_type: &str) -> Event<Value> {
+        parse_event(&event_frame(uri, event_type, json!({})))
+            .unwrap()
+            .unwrap()
+    }
+
+    /// Stands in for the websocket of the Riot Client, recording the messages it receives.
+    ///
+    /// Acknowledges subscriptions with an empty message and sends `frames` after the first
+    /// one. Closes the connection once the client unsubscribes.
+    async fn serve(frames: Vec<String>) -> (WampClient, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let recorded = received.clone();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut socket = accept_async(stream).await.unwrap();
+            let mut frames = Some(frames);
+            while let Some(Ok(Message::Text(text))) = socket.next().await {
+                recorded.lock().unwrap().push(text.clone());
+                socket.send(Message::text("")).await.unwrap();
+                let message: Vec<Value> = serde_json::from_str(&text).unwrap();
+                if message[0] == UNSUBSCRIBE {
+                    socket.close(None).await.unwrap();
+                    break;
+                }
+                for frame in frames.take().unwrap_or_default() {
+                    socket.send(Message::text(frame)).await.unwrap();
+                }
+            }
+        });
+        let (socket, _) = connect_async(url).await.unwrap();
+        (WampClient::new(socket), received)
+    }
+
+    #[test]
+    fn parses_event_frames() {
+        let frame = event_frame("/chat/v4/presences", "Update", json!({ "count": 1 }));
+        let event = parse_event(&frame).unwrap().unwrap();
+        assert_eq!(event.subscription, PRESENCES);
+        assert_eq!(event.uri, "/chat/v4/presences");
+        assert_eq!(event.event_type, "Update");
+        assert_eq!(event.data, json!({ "count": 1 }));
+        // Other opcodes, like the welcome message, aren't events.
+        assert!(parse_event(r#"[0, "session", 1, "server"]"#)
+            .unwrap()
+            .is_none());
+        assert!(parse_event(&json!([SUBSCRIBE, PRESENCES]).to_string())
+            .unwrap()
+            .is_none());
+        assert!(parse_event("not json").is_err());
+        assert!(parse_event(&json!([EVENT, PRESENCES, { "uri": 1 }]).to_string()).is_err());
+    }
+
+    #[test]
+    fn filters_events_by_uri_and_type() {
+        let update = event("/chat/v4/presences", "Update");
+        let delete = event("/chat/v4/presences", "Delete");
+        let other = event("/chat/v5/participants", "Update");
+        let presences = EventFilter::uri("/chat/v4/presences");
+        assert!(presences.matches(&update));
+        assert!(presences.matches(&delete));
+        assert!(!presences.matches(&other));
+        let updates = presences.event_type("Update");
+        assert!(updates.matches(&update));
+        assert!(!updates.matches(&delete));
+        assert!(!updates.matches(&other));
+    }
+
+    #[tokio::test]
+    async fn delivers_events_to_routes_and_listeners() {
+        let frames = vec![
+            event_frame("/chat/v4/presences", "Update", json!({ "count": 1 })),
+            event_frame("/chat/v4/presences", "Delete", json!({ "count": 2 })),
+            event_frame("/chat/v5/participants", "Update", json!({ "count": 3 })),
+            event_frame("/chat/v4/presences", "Update", json!({ "count": "four" })),
+            "not json".to_owned(),
+        ];
+        let (client, received) = serve(frames).await;
+        let mut updates =
+            client.route::<Count>(EventFilter::uri("/chat/v4/presences").event_type("Update"));
+        let mut events = client.events();
+        client.subscribe(JSON_API_EVENT).await.unwrap();
+        client.unsubscribe(JSON_API_EVENT).await.unwrap();
+        client.run().await.unwrap();
+
+        assert_eq!(updates.recv().await.unwrap().unwrap().data.count, 1);
+        let error = updates.recv().await.unwrap().unwrap_err();
+        assert_eq!(error.path, "count");
+        assert_eq!(error.payload, r#"{"count":"four"}"#);
+        assert!(updates.try_recv().is_err());
+        let mut uris = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            uris.push(event.uri);
+        }
+        assert_eq!(
+            uris,
+            [
+                "/chat/v4/presences",
+                "/chat/v4/presences",
+                "/chat/v5/participants",
+                "/chat/v4/presences"
+            ]
+        );
+        assert_eq!(
+            *received.lock().unwrap(),
+            [
+                json!([SUBSCRIBE, JSON_API_EVENT]).to_string(),
+                json!([UNSUBSCRIBE, JSON_API_EVENT]).to_string()
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn drops_routes_and_listeners_whose_receiver_is_gone() {
+        let (client, _) = serve(Vec::new()).await;
+        let _kept = client.route::<Count>(EventFilter::uri("/chat/v4/presences"));
+        drop(client.route::<Count>(EventFilter::uri("/chat/v4/presences")));
+        // Routes which don't match the event are kept, as their receiver isn't asked.
+        drop(client.route::<Count>(EventFilter::uri("/chat/v5/participants")));
+        drop(client.events());
+        client.dispatch(event("/chat/v4/presences", "Update"));
+        assert_eq!(client.routes.lock().unwrap().len(), 2);
+        assert!(client.listeners.lock().unwrap().is_empty());
+    }
+}
@@ -1,129 +1,165 @@
-use anyhow::{Error, Result};
-use base64::{decode, encode};
-use futures::StreamExt;
-use futures_util::{stream::SplitSink, SinkExt};
-use http::{header::AUTHORIZATION, Request};
-use lazy_static::lazy_static;
-use native_tls::TlsConnector;
-use reqwest::Client;
-use tokio::{net::TcpStream, sync::mpsc::Sender};
-use tokio_tungstenite::{
-    connect_async_tls_with_config,
-    tungstenite::{client::IntoClientRequest, handshake::client::Response, Message},
-    Connector, MaybeTlsStream, WebSocketStream,
+use std::{
+    future::pending,
+    sync::Arc,
+    time::{Duration, Instant},
 };
 
+use reqwest::Client;
+use tokio::{sync::mpsc::Sender, time::sleep_until};
+use tracing::{debug, info};
+
 use crate::{
+    error::{Error, Result},
     lockfile::RiotCredentials,
-    valorant::{presence::PresenceResponse, session::SessionResponse},
+    valorant::session::{Session, SessionResponse},
 };
 
 use super::{
+    agents::AgentTracker,
+    api::{RiotApi, RiotClientApi},
+    decode::{decode_private, DecodeReporter, PresenceDecodeError},
     game_state::GameState,
-    presence::{ParsedPresence, Presence},
-    presence_analyzer::analyze_presence,
+    presence::{ParsedPresence, Presence, PresencesEvent},
+    presence_analyzer::PresenceTracker,
+    supervisor::ClientEvent,
+    wamp::{EventFilter, WampClient},
 };
 
+/// Every event the websocket subscribes to, the client has to offer them before we connect.
+pub const SUBSCRIBED_EVENTS: &[&str] = &["OnJsonApiEvent_chat_v4_presences"];
+
+const PRESENCES_URI: &str = "/chat/v4/presences";
+
+const SESSION_LOAD_ATTEMPTS: u32 = 30;
+const SESSION_LOAD_DELAY: Duration = Duration::from_secs(1);
+
 pub async fn receive_websocket_events(
-    sender: Sender<GameState>,
+    sender: &Sender<ClientEvent>,
     creds: RiotCredentials,
+    client: &Client,
+    reporter: &mut DecodeReporter,
+    tracker: &mut PresenceTracker,
 ) -> Result<()> {
-    let own_puuid = get_puuid(&creds).await?;
-    let (socket, _) = create_websocket_connection(&creds).await;
-    println!("Connected to websocket.");
-    let (mut write, read) = futures::StreamExt::split(socket);
-    register_ws_event(&mut write, 5, "OnJsonApiEvent_chat_v4_presences").await?;
-    println!("Registered for OnJsonApiEvent_chat_v4_presences event.");
-    tokio::spawn(async move {
-        read.filter_map(|result| async { result.ok() })
-            .filter_map(|message| async {
-                match message {
-                    Message::Text(text) => Some(text),
-                    _ => None,
+    let session = get_session(client, &creds).await?;
+    let own_puuid = session.puuid.clone();
+    let wamp = WampClient::connect(&creds).await?;
+    info!("Connected to websocket.");
+    let api = Arc::new(RiotClientApi::new(&creds, &own_puuid)?);
+    let _ = sender
+        .send(ClientEvent::Connected {
+            session,
+            api: api.clone(),
+        })
+        .await;
+    let mut agents = AgentTracker::default();
+    let mut presences = wamp.route::<PresencesEvent>(EventFilter::uri(PRESENCES_URI));
+    for event in SUBSCRIBED_EVENTS {
+        wamp.subscribe(event).await?;
+        info!("Registered for {} event.", event);
+    }
+    let handle_events = async {
+        let mut last_state: Option<GameState> = None;
+        loop {
+            let next_lookup = last_state.as_ref().and(agents.next_lookup());
+            tokio::select! {
+                event = presences.recv() => match event {
+                    Some(Ok(event)) => {
+                        let state = handle_presences(
+                            sender,
+                            event.data.presences,
+                            &own_puuid,
+                            reporter,
+                            tracker,
+                            api.as_ref(),
+                            &mut agents,
+                        )
+                        .await;
+                        last_state = state.or(last_state);
+                    }
+                    Some(Err(e)) => {
+                        let payload = e.payload.clone();
+                        reporter.report(&PresenceDecodeError::Event(e), &payload);
+                    }
+                    None => break,
+                },
+                // Picking an agent doesn't change the presence, so it is looked up on a timer.
+                _ = lookup_due(next_lookup) => {
+                    if let Some(state) = &mut last_state {
+                        let agent = agents.resolve(api.as_ref(), &state.status).await;
+                        if agent != state.agent {
+                            state.agent = agent;
+                            let _ = sender.send(ClientEvent::State(Box::new(state.clone()))).await;
+                        }
+                    }
                 }
-            })
-            .filter_map(
-                |message| async move { serde_json::from_str::<PresenceResponse>(&message).ok() },
-            )
-            .map(|response| response.data.data.presences)
-            .for_each(|value| async {
-                handle_presences(&sender, value, own_puuid.as_str()).await;
-            })
-            .await;
-    });
-    Ok(())
+            }
+        }
+    };
+    tokio::select! {
+        result = wamp.run() => result,
+        _ = handle_events => Ok(()),
+    }
 }
 
-async fn handle_presences(sender: &Sender<GameState>, presences: Vec<Presence>, own_puuid: &str) {
+async fn handle_presences(
+    sender: &Sender<ClientEvent>,
+    presences: Vec<Presence>,
+    own_puuid: &str,
+    reporter: &mut DecodeReporter,
+    tracker: &mut PresenceTracker,
+    api: &dyn RiotApi,
+    agents: &mut AgentTracker,
+) -> Option<GameState> {
     let presences: Vec<ParsedPresence> = presences
         .iter()
         .filter(|p| p.product == "valorant" && p.puuid == own_puuid)
-        .filter_map(|p| decode(&p.private).ok())
-        .filter_map(|json| String::from_utf8(json).ok())
-        .filter_map(|json| serde_json::from_str::<ParsedPresence>(&json).ok())
+        .filter_map(|p| match decode_private(p) {
+            Ok(presence) => Some(presence),
+            Err(e) => {
+                reporter.report(&e, &p.private);
+                None
+            }
+        })
         .collect();
     let presence = match presences.first() {
         Some(p) => p,
-        None => return,
+        None => return None,
     };
 
-    let presence = analyze_presence(presence);
-    sender.send(presence).await.unwrap();
-}
-
-async fn register_ws_event(
-    socket: &mut SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>,
-    event_number: u32,
-    event_name: &str,
-) -> Result<(), Error> {
-    let message = Message::text(format!("[{},\"{}\"]", event_number, event_name));
-    Ok(socket.send(message).await?)
-}
-
-async fn create_websocket_connection(
-    creds: &RiotCredentials,
-) -> (WebSocketStream<MaybeTlsStream<TcpStream>>, Response) {
-    let request = build_request(creds);
-    connect_async_tls_with_config(request, None, Some(build_ssl_config()))
-        .await
-        .expect("Failed to connect")
-}
-
-fn build_request(creds: &RiotCredentials) -> Request<()> {
-    let host = format!("wss://127.0.0.1:{}", creds.port);
-    let mut request = host.into_client_request().unwrap();
-    let basic_auth = encode(format!("riot:{}", creds.password));
-    request.headers_mut().insert(
-        AUTHORIZATION,
-        format!("Basic {}", basic_auth).parse().unwrap(),
-    );
-    request
-}
-
-fn build_ssl_config() -> Connector {
-    Connector::NativeTls(
-        TlsConnector::builder()
-            .danger_accept_invalid_certs(true)
-            .build()
-            .unwrap(),
-    )
+    let mut state = tracker.analyze(presence);
+    state.agent = agents.resolve(api, &state.status).await;
+    // The receiver only goes away when the app shuts down.
+    let _ = sender
+        .send(ClientEvent::State(Box::new(state.clone())))
+        .await;
+    Some(state)
 }
 
-lazy_static! {
-    static ref CLIENT: Client = reqwest::Client::builder()
-        .danger_accept_invalid_certs(true)
-        .build()
-        .unwrap();
+/// Waits until the agent is due to be looked up again, forever if it isn't.
+async fn lookup_due(at: Option<Instant>) {
+    match at {
+        Some(at) => sleep_until(at.into()).await,
+        None => pending().await,
+    }
 }
 
-pub async fn get_puuid(creds: &RiotCredentials) -> Result<String> {
-    let response: SessionResponse = CLIENT
-        .get(format!("https://127.0.0.1:{}/chat/v1/session", creds.port))
-        .basic_auth("riot", Some(&creds.password))
-        .send()
-        .await?
-        .json()
-        .await?;
-
-    Ok(response.puuid)
+/// Fetches the chat session, waiting for the client to finish loading it.
+pub async fn get_session(client: &Client, creds: &RiotCredentials) -> Result<Session> {
+    for _ in 0..SESSION_LOAD_ATTEMPTS {
+        let response: SessionResponse = client
+            .get(format!("https://127.0.0.1:{}/chat/v1/session", creds.port))
+            .basic_auth("riot", Some(&creds.password))
+            .send()
+            .await?
+            .json()
+            .await?;
+        if response.loaded {
+            return Ok(response.into());
+        }
+        debug!("Chat session is not loaded yet. Waiting...");
+        tokio::time::sleep(SESSION_LOAD_DELAY).await;
+    }
+    Err(Error::RiotClient(
+        "chat session did not finish loading".to_owned(),
+    ))
 }